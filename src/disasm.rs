@@ -0,0 +1,289 @@
+use std::fmt;
+use wasm_bindgen::prelude::*;
+
+use crate::instruction::{decode, Instruction};
+
+/// Address programs are loaded at.
+pub const ROM_START: u16 = 0x200;
+
+/// What the tracer concluded a byte of the ROM is used for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteKind {
+    /// Reached by following control flow from the entry point.
+    Code,
+    /// Drawn with Dxyn after I was pointed at it.
+    Sprite,
+    /// Neither reached nor drawn; could be anything.
+    Data,
+}
+
+/// One row of a listing: an instruction, or a single byte of data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListingLine {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub kind: ByteKind,
+    pub text: String,
+}
+
+impl fmt::Display for ListingLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let raw: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(
+            f,
+            "{:#05X}  {:<11}  {}",
+            self.address,
+            raw.join(" "),
+            self.text
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Listing {
+    pub lines: Vec<ListingLine>,
+    kinds: Vec<ByteKind>,
+}
+
+impl Listing {
+    /// The classification of the byte at `address`, if it is inside the ROM.
+    pub fn kind_at(&self, address: u16) -> Option<ByteKind> {
+        let offset = address.checked_sub(ROM_START)? as usize;
+        self.kinds.get(offset).copied()
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Disassembles a ROM loaded at `ROM_START`, tracing control flow from the
+/// entry point to separate code from sprite data.
+pub fn disassemble(rom: &[u8]) -> Listing {
+    let kinds = trace(rom);
+    let mut lines = Vec::new();
+    let mut offset = 0;
+
+    while offset < rom.len() {
+        let address = ROM_START + offset as u16;
+        let line = match kinds[offset] {
+            ByteKind::Code if offset + 1 < rom.len() => {
+                let instruction = decode(read_word(rom, offset));
+                let len = instruction_len(instruction).min(rom.len() - offset);
+                let text = match instruction {
+                    Instruction::LoadLongI if len == 4 => {
+                        format!("LD I, {:#06X}", read_word(rom, offset + 2))
+                    }
+                    _ => instruction.to_string(),
+                };
+                ListingLine {
+                    address,
+                    bytes: rom[offset..offset + len].to_vec(),
+                    kind: ByteKind::Code,
+                    text,
+                }
+            }
+            kind => ListingLine {
+                address,
+                bytes: vec![rom[offset]],
+                kind,
+                text: data_text(rom[offset], kind),
+            },
+        };
+        offset += line.bytes.len();
+        lines.push(line);
+    }
+
+    Listing { lines, kinds }
+}
+
+/// Text listing of `rom`, for the web debugger.
+#[wasm_bindgen]
+pub fn disassemble_rom(rom: &[u8]) -> String {
+    disassemble(rom).to_string()
+}
+
+/// Linear disassembly of `count` instructions starting at `address`, without
+/// any tracing. Used to show the code around the PC of a running machine.
+pub fn disassemble_memory(memory: &[u8], address: u16, count: usize) -> Vec<ListingLine> {
+    let mut lines = Vec::with_capacity(count);
+    let mut offset = address as usize;
+
+    while lines.len() < count && offset + 1 < memory.len() {
+        let instruction = decode(read_word(memory, offset));
+        let len = instruction_len(instruction).min(memory.len() - offset);
+        lines.push(ListingLine {
+            address: offset as u16,
+            bytes: memory[offset..offset + len].to_vec(),
+            kind: ByteKind::Code,
+            text: instruction.to_string(),
+        });
+        offset += len;
+    }
+    lines
+}
+
+fn data_text(byte: u8, kind: ByteKind) -> String {
+    match kind {
+        ByteKind::Sprite => {
+            let pixels: String = (0..8)
+                .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+                .collect();
+            format!("DB {:#04X}  ; {}", byte, pixels)
+        }
+        _ => format!("DB {:#04X}", byte),
+    }
+}
+
+fn read_word(bytes: &[u8], offset: usize) -> u16 {
+    (bytes[offset] as u16) << 8 | bytes[offset + 1] as u16
+}
+
+fn instruction_len(instruction: Instruction) -> usize {
+    match instruction {
+        Instruction::LoadLongI => 4,
+        _ => 2,
+    }
+}
+
+/// Follows every path from the entry point, marking the bytes of each
+/// instruction reached as code and the bytes each Dxyn draws as sprites.
+fn trace(rom: &[u8]) -> Vec<ByteKind> {
+    let mut kinds = vec![ByteKind::Data; rom.len()];
+    let mut sprites = vec![false; rom.len()];
+    let mut visited = vec![false; rom.len()];
+    let mut pending: Vec<(usize, Option<usize>)> = vec![(0, None)];
+
+    let offset_of = |address: u16| (address as usize).checked_sub(ROM_START as usize);
+
+    while let Some((offset, mut i)) = pending.pop() {
+        if offset + 1 >= rom.len() || visited[offset] {
+            continue;
+        }
+        visited[offset] = true;
+
+        let instruction = decode(read_word(rom, offset));
+        let len = instruction_len(instruction).min(rom.len() - offset);
+        for kind in &mut kinds[offset..offset + len] {
+            *kind = ByteKind::Code;
+        }
+
+        let next = offset + len;
+        let skip_len = match rom.get(next..next + 2) {
+            Some([0xF0, 0x00]) => 4,
+            _ => 2,
+        };
+
+        use Instruction::*;
+        match instruction {
+            Jump { nnn } => pending.extend(offset_of(nnn).map(|target| (target, i))),
+            Call { nnn } => {
+                pending.extend(offset_of(nnn).map(|target| (target, i)));
+                pending.push((next, i));
+            }
+            Ret | Exit | JumpWithOffset { .. } | Unknown(_) => {}
+            SkipIfEqual { .. }
+            | SkipIfNotEqual { .. }
+            | SkipIfRegistersEqual { .. }
+            | SkipIfRegistersNotEqual { .. }
+            | SkipIfKeyPressed { .. }
+            | SkipIfKeyNotPressed { .. } => {
+                pending.push((next, i));
+                pending.push((next + skip_len, i));
+            }
+            LoadI { nnn } => pending.push((next, offset_of(nnn))),
+            LoadLongI if len == 4 => pending.push((next, offset_of(read_word(rom, offset + 2)))),
+            Draw { n, .. } => {
+                if let Some(start) = i {
+                    let sprite_len = if n == 0 { 32 } else { n as usize };
+                    let end = (start + sprite_len).min(rom.len());
+                    for sprite in sprites.iter_mut().take(end).skip(start) {
+                        *sprite = true;
+                    }
+                }
+                pending.push((next, i));
+            }
+            AddToI { .. } | LoadFont { .. } | LoadBigFont { .. } => {
+                i = None;
+                pending.push((next, i));
+            }
+            _ => pending.push((next, i)),
+        }
+    }
+
+    for (kind, sprite) in kinds.iter_mut().zip(sprites) {
+        if *kind == ByteKind::Data && sprite {
+            *kind = ByteKind::Sprite;
+        }
+    }
+    kinds
+}
+
+#[cfg(test)]
+mod disasm_tests {
+    use super::*;
+
+    // 200: LD I, 0x208 / 202: DRW V0, V1, 2 / 204: JP 0x204 / 206: (never run)
+    // 208: two sprite rows
+    const ROM: [u8; 10] = [0xA2, 0x08, 0xD0, 0x12, 0x12, 0x04, 0x12, 0x34, 0xF0, 0x90];
+
+    #[test]
+    fn it_lists_addresses_bytes_and_mnemonics() {
+        let listing = disassemble(&ROM);
+        assert_eq!(listing.lines[0].address, 0x200);
+        assert_eq!(listing.lines[0].bytes, vec![0xA2, 0x08]);
+        assert_eq!(listing.lines[0].text, "LD I, 0x208");
+        assert_eq!(listing.lines[1].text, "DRW V0, V1, 2");
+        assert_eq!(listing.lines[2].text, "JP 0x204");
+        assert_eq!(
+            listing.to_string().lines().next(),
+            Some("0x200  A2 08        LD I, 0x208")
+        );
+    }
+
+    #[test]
+    fn it_classifies_code_sprites_and_data() {
+        let listing = disassemble(&ROM);
+        assert_eq!(listing.kind_at(0x200), Some(ByteKind::Code));
+        assert_eq!(listing.kind_at(0x205), Some(ByteKind::Code));
+        assert_eq!(listing.kind_at(0x206), Some(ByteKind::Data));
+        assert_eq!(listing.kind_at(0x208), Some(ByteKind::Sprite));
+        assert_eq!(listing.kind_at(0x209), Some(ByteKind::Sprite));
+        assert_eq!(listing.kind_at(0x20A), None);
+        assert_eq!(listing.lines[5].text, "DB 0xF0  ; ####....");
+    }
+
+    #[test]
+    fn it_follows_both_sides_of_skips_and_calls() {
+        // 200: SE V0, 0 / 202: JP 0x208 / 204: CALL 0x20A / 206: EXIT
+        // 208: EXIT / 20A: RET
+        let rom = [
+            0x30, 0x00, 0x12, 0x08, 0x22, 0x0A, 0x00, 0xFD, 0x00, 0xFD, 0x00, 0xEE,
+        ];
+        let listing = disassemble(&rom);
+        assert!((0..rom.len() as u16).all(|i| listing.kind_at(0x200 + i) == Some(ByteKind::Code)));
+    }
+
+    #[test]
+    fn it_decodes_long_i_loads_as_one_line() {
+        let rom = [0xF0, 0x00, 0x02, 0x06, 0x00, 0xFD, 0xFF];
+        let listing = disassemble(&rom);
+        assert_eq!(listing.lines[0].text, "LD I, 0x0206");
+        assert_eq!(listing.lines[0].bytes.len(), 4);
+        assert_eq!(listing.lines[1].address, 0x204);
+    }
+
+    #[test]
+    fn it_disassembles_memory_linearly() {
+        let mut memory = [0u8; 0x210];
+        memory[0x200..0x20A].copy_from_slice(&ROM);
+        let lines = disassemble_memory(&memory, 0x202, 3);
+        let text: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, vec!["DRW V0, V1, 2", "JP 0x204", "JP 0x234"]);
+    }
+}
@@ -2,6 +2,9 @@ use std::fmt;
 use wasm_bindgen::prelude::*;
 
 use super::utils;
+#[cfg(feature = "png")]
+use crate::render::{render_rgba, Palette};
+use crate::state::{SaveStateError, StateReader, StateWriter};
 
 #[wasm_bindgen]
 #[repr(u8)]
@@ -11,12 +14,83 @@ pub enum Pixel {
     On = 1,
 }
 
+/// Number of XO-CHIP drawing planes.
+pub const PLANE_COUNT: usize = 2;
+
+/// Tallest supported display, in rows.
+const MAX_HEIGHT: usize = 64;
+
+/// One row of one plane, packed one bit per pixel. Column x is bit `127 - x`,
+/// so a sprite byte lines up with its position in a single shift, and the
+/// 64-pixel rows of the low resolution mode use the upper half.
+type Row = u128;
+const ROW_BITS: u32 = Row::BITS;
+
+/// The sizes a program can switch the display between.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    /// 64x32, the original CHIP-8 display.
+    Lores,
+    /// 128x64, the SUPER-CHIP high-resolution mode.
+    Hires,
+}
+
+impl Resolution {
+    /// Width and height in pixels.
+    pub fn size(self) -> (u32, u32) {
+        match self {
+            Resolution::Lores => (64, 32),
+            Resolution::Hires => (128, 64),
+        }
+    }
+
+    fn from_size(size: (u32, u32)) -> Option<Resolution> {
+        [Resolution::Lores, Resolution::Hires]
+            .iter()
+            .copied()
+            .find(|resolution| resolution.size() == size)
+    }
+}
+
+/// Ways of drawing the display as text, from one character per pixel to
+/// eight.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextStyle {
+    /// A ◼ or ◻ for every pixel.
+    #[default]
+    Squares,
+    /// A braille character for every 2x4 pixels, so 64x32 fits in 32x8.
+    Braille,
+    /// A ▀, ▄, █ or space for every 1x2 pixels.
+    HalfBlocks,
+}
+
+/// The braille dot for each pixel of a 2x4 cell, by row and column.
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+const BRAILLE_BLANK: u32 = 0x2800;
+
+/// A rectangle of pixels that changed, in display coordinates.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DirtyRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
 #[wasm_bindgen]
 #[derive(Debug)]
 pub struct Display {
     width: u32,
     height: u32,
-    pixels: Vec<Pixel>,
+    planes: [[Row; MAX_HEIGHT]; PLANE_COUNT],
+    plane_mask: u8,
+    /// Pixels that changed on any plane since `take_dirty_regions` or
+    /// `clear_dirty`.
+    dirty: [Row; MAX_HEIGHT],
 }
 
 #[wasm_bindgen]
@@ -41,25 +115,99 @@ impl Display {
 
     pub fn new_empty() -> Display {
         utils::set_panic_hook();
-        let width = 64;
-        let height = 32;
-        let pixels = (0..width * height).map(|_| Pixel::Off).collect();
-
-        Display::new(width, height, Some(pixels))
+        Display::new(64, 32, None)
     }
 
     fn new(width: u32, height: u32, pixels: Option<Vec<Pixel>>) -> Display {
-        Display {
+        let mut display = Display {
             width,
             height,
-            pixels: pixels.unwrap_or(Vec::with_capacity((width * height) as usize)),
+            planes: [[0; MAX_HEIGHT]; PLANE_COUNT],
+            plane_mask: 1,
+            dirty: [0; MAX_HEIGHT],
+        };
+        display.mark_all_dirty();
+        for (i, pixel) in pixels.unwrap_or_default().into_iter().enumerate() {
+            display.set_pixel(0, i, pixel == Pixel::On);
         }
+        display
     }
 
     pub fn render(&self) -> String {
         self.to_string()
     }
 
+    /// Draws the screen as lines of text in `style`, counting a pixel lit
+    /// on any plane.
+    pub fn render_text(&self, style: TextStyle) -> String {
+        let mut text = String::new();
+        match style {
+            TextStyle::Squares => return self.to_string(),
+            TextStyle::Braille => {
+                for top in (0..self.height).step_by(4) {
+                    for left in (0..self.width).step_by(2) {
+                        let mut dots = 0;
+                        for (row, cells) in (top..).zip(BRAILLE_DOTS.iter()) {
+                            for (column, &dot) in (left..).zip(cells.iter()) {
+                                if self.is_any_lit(row, column) {
+                                    dots |= dot;
+                                }
+                            }
+                        }
+                        text.extend(std::char::from_u32(BRAILLE_BLANK + dots));
+                    }
+                    text.push('\n');
+                }
+            }
+            TextStyle::HalfBlocks => {
+                for top in (0..self.height).step_by(2) {
+                    for column in 0..self.width {
+                        let upper = self.is_any_lit(top, column);
+                        let lower = self.is_any_lit(top + 1, column);
+                        text.push(match (upper, lower) {
+                            (true, true) => '█',
+                            (true, false) => '▀',
+                            (false, true) => '▄',
+                            (false, false) => ' ',
+                        });
+                    }
+                    text.push('\n');
+                }
+            }
+        }
+        text
+    }
+
+    /// Encodes the screen as an RGBA PNG, each pixel `scale` x `scale`.
+    #[cfg(feature = "png")]
+    pub fn to_png(&self, scale: u32, palette: &Palette) -> Vec<u8> {
+        let scale = scale.max(1);
+        let mut rgba = Vec::new();
+        render_rgba(self, palette, scale, &mut rgba);
+
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, self.width * scale, self.height * scale);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&rgba))
+            .expect("encoding to memory cannot fail");
+        png
+    }
+
+    /// Encodes the screen as a binary (P4) PBM, with a 1 bit, drawn black,
+    /// for every pixel lit on any plane.
+    pub fn to_pbm(&self) -> Vec<u8> {
+        let mut pbm = format!("P4\n{} {}\n", self.width, self.height).into_bytes();
+        let row_bytes = (self.width as usize).div_ceil(8);
+        for y in 0..self.height as usize {
+            let lit = self.planes.iter().fold(0, |row, plane| row | plane[y]);
+            pbm.extend_from_slice(&lit.to_be_bytes()[..row_bytes]);
+        }
+        pbm
+    }
+
     pub fn width(&self) -> u32 {
         self.width
     }
@@ -68,60 +216,265 @@ impl Display {
         self.height
     }
 
-    pub fn pixels(&self) -> *const Pixel {
-        self.pixels.as_ptr()
+    pub fn plane_mask(&self) -> u8 {
+        self.plane_mask
     }
 
+    /// Selects the planes that drawing, clearing and scrolling act on, as a
+    /// bitmask with bit 0 for the first plane.
+    pub fn select_planes(&mut self, mask: u8) {
+        self.plane_mask = mask & ((1 << PLANE_COUNT) - 1);
+    }
+
+    pub fn selected_plane_count(&self) -> u32 {
+        self.plane_mask.count_ones()
+    }
+
+    /// Clears the selected planes.
     pub fn cls(&mut self) {
-        self.pixels = (0..self.width * self.height).map(|_| Pixel::Off).collect();
+        for plane in self.selected_planes() {
+            for (dirty, row) in self.dirty.iter_mut().zip(&self.planes[plane]) {
+                *dirty |= row;
+            }
+            self.planes[plane] = [0; MAX_HEIGHT];
+        }
+    }
+
+    /// Switches to `resolution`, clearing every plane.
+    pub fn resize(&mut self, resolution: Resolution) {
+        let (width, height) = resolution.size();
+        self.width = width;
+        self.height = height;
+        self.planes = [[0; MAX_HEIGHT]; PLANE_COUNT];
+        self.mark_all_dirty();
     }
 
     pub fn toggle_pixel(&mut self, i: usize) {
-        self.pixels[i] = if self.pixels[i] == Pixel::Off {
+        self.set_pixel(0, i, !self.is_lit(0, i));
+    }
+
+    pub fn get_pixel(&self, i: usize) -> Pixel {
+        if self.is_lit(0, i) {
             Pixel::On
         } else {
             Pixel::Off
-        };
+        }
     }
 
-    pub fn get_pixel(&self, i: usize) -> Pixel {
-        self.pixels[i]
+    /// The colour index of a pixel, with bit n set when plane n is lit.
+    pub fn get_color(&self, i: usize) -> u8 {
+        (0..PLANE_COUNT)
+            .map(|plane| (self.is_lit(plane, i) as u8) << plane)
+            .sum()
+    }
+
+    /// XORs `bytes` onto the screen as an 8-pixel-wide sprite with its top
+    /// left corner at (`x`, `y`), returning whether any lit pixel was erased.
+    ///
+    /// `bytes` holds one sprite per selected plane, back to back. The origin
+    /// always wraps; pixels that run off the edge either wrap to the opposite
+    /// side or are dropped when `clip` is set.
+    pub fn draw_bytes(&mut self, x: u8, y: u8, bytes: &[u8], clip: bool) -> bool {
+        self.draw_sprite(x, y, bytes, 1, clip)
     }
 
-    pub fn draw_bytes(&mut self, x: u8, y: u8, bytes: &[u8]) -> bool {
-        let bits: Vec<[bool; 8]> = bytes.into_iter().map(Display::to_bool_array).collect();
+    /// Like `draw_bytes`, but for the 16x16 SUPER-CHIP sprites that use two
+    /// bytes per row.
+    pub fn draw_wide_bytes(&mut self, x: u8, y: u8, bytes: &[u8], clip: bool) -> bool {
+        self.draw_sprite(x, y, bytes, 2, clip)
+    }
+
+    pub fn scroll_up(&mut self, rows: u32) {
+        self.shift(0, -(rows as i32));
+    }
+
+    pub fn scroll_down(&mut self, rows: u32) {
+        self.shift(0, rows as i32);
+    }
+
+    pub fn scroll_left(&mut self, columns: u32) {
+        self.shift(-(columns as i32), 0);
+    }
+
+    pub fn scroll_right(&mut self, columns: u32) {
+        self.shift(columns as i32, 0);
+    }
+
+    /// Whether any pixel changed since the last `take_dirty_regions` or
+    /// `clear_dirty`.
+    pub fn changed_since_last_frame(&self) -> bool {
+        self.dirty.iter().any(|&row| row != 0)
+    }
+
+    /// Forgets the changes so far without listing them, for frontends that
+    /// redraw the whole screen.
+    pub fn clear_dirty(&mut self) {
+        self.dirty = [0; MAX_HEIGHT];
+    }
+
+    /// The areas that changed since the last call, as one rectangle per run
+    /// of changed rows spanning their changed columns. A new, reloaded or
+    /// resized display is dirty everywhere.
+    pub fn take_dirty_regions(&mut self) -> Vec<DirtyRegion> {
+        let mut regions: Vec<DirtyRegion> = Vec::new();
+        let mut run: Option<(u32, Row)> = None;
+        for y in 0..=self.height {
+            let row = self.dirty.get(y as usize).copied().unwrap_or(0);
+            run = match (run, row) {
+                (Some((top, columns)), 0) => {
+                    let x = columns.leading_zeros();
+                    let right = ROW_BITS - columns.trailing_zeros();
+                    regions.push(DirtyRegion {
+                        x,
+                        y: top,
+                        width: right - x,
+                        height: y - top,
+                    });
+                    None
+                }
+                (Some((top, columns)), row) => Some((top, columns | row)),
+                (None, 0) => None,
+                (None, row) => Some((y, row)),
+            };
+        }
+        self.clear_dirty();
+        regions
+    }
+
+    fn selected_planes(&self) -> impl Iterator<Item = usize> {
+        let mask = self.plane_mask;
+        (0..PLANE_COUNT).filter(move |plane| mask & (1 << plane) != 0)
+    }
+
+    fn draw_sprite(&mut self, x: u8, y: u8, bytes: &[u8], row_bytes: usize, clip: bool) -> bool {
+        let plane_count = self.selected_plane_count() as usize;
+        if plane_count == 0 || bytes.is_empty() {
+            return false;
+        }
+
+        let sprite_len = bytes.len() / plane_count;
         let mut collision_flag = false;
+        for (plane, sprite) in self.selected_planes().zip(bytes.chunks(sprite_len)) {
+            collision_flag |= self.draw_plane(plane, x, y, sprite, row_bytes, clip);
+        }
+        collision_flag
+    }
 
-        for (i_y, pos_y) in (y..(y + bytes.len() as u8)).enumerate() {
-            for (i_x, pos_x) in (x..(x + 8)).enumerate() {
-                let idx = self.get_index(pos_y as u32 % self.height, pos_x as u32 % self.width);
-                self.pixels[idx] = match (self.pixels[idx], bits[i_y][i_x]) {
-                    (Pixel::On, true) => {
-                        collision_flag = true;
-                        Pixel::Off
-                    }
-                    (Pixel::Off, false) => Pixel::Off,
-                    (Pixel::On, false) => Pixel::On,
-                    (Pixel::Off, true) => Pixel::On,
-                };
+    fn draw_plane(
+        &mut self,
+        plane: usize,
+        x: u8,
+        y: u8,
+        bytes: &[u8],
+        row_bytes: usize,
+        clip: bool,
+    ) -> bool {
+        let mut collision_flag = false;
+        let x = x as u32 % self.width;
+        let y = y as u32 % self.height;
+        let mask = self.row_mask();
+
+        for (i_y, row) in bytes.chunks(row_bytes).enumerate() {
+            let pos_y = y + i_y as u32;
+            if clip && pos_y >= self.height {
+                break;
             }
+            // The sprite row, left-aligned in a `Row`.
+            let sprite = row
+                .iter()
+                .fold(0, |bits: Row, &byte| bits << 8 | byte as Row)
+                << (ROW_BITS as usize - 8 * row.len());
+            let mut line = sprite >> x;
+            if !clip {
+                // Pixels past the right edge come back in on the left.
+                line |= sprite.checked_shl(self.width - x).unwrap_or(0);
+            }
+            line &= mask;
+
+            let target = &mut self.planes[plane][(pos_y % self.height) as usize];
+            collision_flag |= *target & line != 0;
+            *target ^= line;
+            self.dirty[(pos_y % self.height) as usize] |= line;
         }
         collision_flag
     }
 
-    fn to_bool_array(bits: &u8) -> [bool; 8] {
-        let mut bool_array: [bool; 8] = [false; 8];
-        for i in 0..8 {
-            bool_array[i] = ((bits >> (7 - i)) & 1) != 0;
+    /// Moves the image on the selected planes by (`dx`, `dy`), filling the
+    /// uncovered area with unlit pixels.
+    fn shift(&mut self, dx: i32, dy: i32) {
+        let height = self.height as usize;
+        let rows = dy.unsigned_abs() as usize;
+        let columns = dx.unsigned_abs();
+        let mask = self.row_mask();
+        for plane in self.selected_planes() {
+            let before = self.planes[plane];
+            let plane = &mut self.planes[plane][..height];
+            let rows = rows.min(height);
+            if dy > 0 {
+                plane.copy_within(..height - rows, rows);
+                plane[..rows].fill(0);
+            } else if dy < 0 {
+                plane.copy_within(rows.., 0);
+                plane[height - rows..].fill(0);
+            }
+            for row in plane.iter_mut() {
+                *row = if dx > 0 {
+                    row.checked_shr(columns).unwrap_or(0) & mask
+                } else {
+                    row.checked_shl(columns).unwrap_or(0)
+                };
+            }
+            for ((dirty, old), new) in self.dirty.iter_mut().zip(&before).zip(plane.iter()) {
+                *dirty |= old ^ new;
+            }
         }
-
-        bool_array
     }
 
     fn get_index(&self, row: u32, column: u32) -> usize {
         (row * self.width + column) as usize
     }
 
+    fn mark_all_dirty(&mut self) {
+        let mask = self.row_mask();
+        for (y, row) in self.dirty.iter_mut().enumerate() {
+            *row = if y < self.height as usize { mask } else { 0 };
+        }
+    }
+
+    fn row_mask(&self) -> Row {
+        Row::MAX.checked_shl(ROW_BITS - self.width).unwrap_or(0)
+    }
+
+    fn column_bit(column: u32) -> Row {
+        1 << (ROW_BITS - 1 - column)
+    }
+
+    fn is_lit(&self, plane: usize, i: usize) -> bool {
+        let (row, column) = (i / self.width as usize, i as u32 % self.width);
+        self.planes[plane][row] & Display::column_bit(column) != 0
+    }
+
+    /// Whether (`column`, `row`) is lit on any plane; false off the screen.
+    fn is_any_lit(&self, row: u32, column: u32) -> bool {
+        row < self.height
+            && column < self.width
+            && self
+                .planes
+                .iter()
+                .any(|plane| plane[row as usize] & Display::column_bit(column) != 0)
+    }
+
+    fn set_pixel(&mut self, plane: usize, i: usize, lit: bool) {
+        let (row, column) = (i / self.width as usize, i as u32 % self.width);
+        let bit = Display::column_bit(column);
+        if lit {
+            self.planes[plane][row] |= bit;
+        } else {
+            self.planes[plane][row] &= !bit;
+        }
+        self.dirty[row] |= bit;
+    }
+
     fn live_neighbor_count(&self, row: u32, column: u32) -> u8 {
         let mut count = 0;
         for delta_row in [self.height - 1, 0, 1].iter().cloned() {
@@ -133,19 +486,19 @@ impl Display {
                 let neighbor_row = (row + delta_row) % self.height;
                 let neighbor_col = (column + delta_col) % self.width;
                 let idx = self.get_index(neighbor_row, neighbor_col);
-                count += self.pixels[idx] as u8;
+                count += self.is_lit(0, idx) as u8;
             }
         }
         count
     }
 
     pub fn tick(&mut self) {
-        let mut next = self.pixels.clone();
+        let mut next = self.planes[0];
 
         for row in 0..self.height {
             for col in 0..self.width {
                 let idx = self.get_index(row, col);
-                let cell = self.pixels[idx];
+                let cell = self.get_pixel(idx);
                 let live_neighbors = self.live_neighbor_count(row, col);
 
                 let next_cell = match (cell, live_neighbors) {
@@ -165,22 +518,79 @@ impl Display {
                     (otherwise, _) => otherwise,
                 };
 
-                next[idx] = next_cell;
+                let bit = Display::column_bit(col);
+                if next_cell == Pixel::On {
+                    next[row as usize] |= bit;
+                } else {
+                    next[row as usize] &= !bit;
+                }
             }
         }
 
-        self.pixels = next;
+        for ((dirty, old), new) in self.dirty.iter_mut().zip(&self.planes[0]).zip(&next) {
+            *dirty |= old ^ new;
+        }
+        self.planes[0] = next;
+    }
+}
+
+impl Display {
+    /// Replaces the contents of `out` with `plane`, one `Pixel` value per
+    /// byte in row-major order. Plane 0 is all a plain CHIP-8 or SUPER-CHIP
+    /// program ever draws to.
+    ///
+    /// Reusing `out` between calls avoids allocating once it has grown to
+    /// the screen size.
+    pub fn pixels_into(&self, plane: usize, out: &mut Vec<u8>) {
+        out.clear();
+        out.extend((0..(self.width * self.height) as usize).map(|i| self.is_lit(plane, i) as u8));
+    }
+
+    pub(crate) fn save(&self, writer: &mut StateWriter) {
+        writer.u16(self.width as u16);
+        writer.u16(self.height as u16);
+        writer.u8(self.plane_mask);
+        let mut pixels = Vec::new();
+        for plane in 0..PLANE_COUNT {
+            self.pixels_into(plane, &mut pixels);
+            writer.bytes(&pixels);
+        }
+    }
+
+    pub(crate) fn load(reader: &mut StateReader) -> Result<Display, SaveStateError> {
+        let width = reader.u16()? as u32;
+        let height = reader.u16()? as u32;
+        if Resolution::from_size((width, height)).is_none() {
+            return Err(SaveStateError::Invalid("display size"));
+        }
+
+        let mut display = Display::new(width, height, None);
+        display.plane_mask = reader.u8()?;
+        if display.plane_mask >= 1 << PLANE_COUNT {
+            return Err(SaveStateError::Invalid("plane mask"));
+        }
+        let size = (width * height) as usize;
+        for plane in 0..PLANE_COUNT {
+            for (i, &byte) in reader.bytes(size)?.iter().enumerate() {
+                match byte {
+                    0 | 1 => display.set_pixel(plane, i, byte == 1),
+                    _ => return Err(SaveStateError::Invalid("pixel")),
+                }
+            }
+        }
+        Ok(display)
     }
 }
 
 impl fmt::Display for Display {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for line in self.pixels.as_slice().chunks(self.width as usize) {
-            for &cell in line {
-                let symbol = if cell == Pixel::Off { '◻' } else { '◼' };
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let lit = self.get_color(self.get_index(row, col)) != 0;
+                let symbol = if lit { '◼' } else { '◻' };
                 write!(f, "{}", symbol)?;
             }
-            write!(f, "\n")?;
+            writeln!(f)?;
         }
 
         Ok(())
@@ -206,10 +616,36 @@ pub static FONT_SET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+/// The SUPER-CHIP 8x10 font, extended with A-F as in Octo.
+pub static BIG_FONT_SET: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
 #[cfg(test)]
 mod display_tests {
     use super::*;
 
+    fn lit_pixels(display: &Display) -> usize {
+        let mut pixels = Vec::new();
+        display.pixels_into(0, &mut pixels);
+        pixels.iter().filter(|&&p| p == Pixel::On as u8).count()
+    }
+
     #[test]
     fn it_gets_zero_index() {
         let test_disp = Display::new(6, 6, None);
@@ -221,4 +657,347 @@ mod display_tests {
         let test_disp = Display::new(6, 6, None);
         assert_eq!(test_disp.get_index(0, 5), 5)
     }
+
+    #[test]
+    fn it_wraps_sprites_past_the_edge() {
+        let mut test_disp = Display::new_empty();
+        test_disp.draw_bytes(62, 31, &[0xFF, 0xFF], false);
+        assert_eq!(test_disp.get_pixel(test_disp.get_index(31, 63)), Pixel::On);
+        assert_eq!(test_disp.get_pixel(test_disp.get_index(31, 0)), Pixel::On);
+        assert_eq!(test_disp.get_pixel(test_disp.get_index(0, 5)), Pixel::On);
+    }
+
+    #[test]
+    fn it_clips_sprites_past_the_edge() {
+        let mut test_disp = Display::new_empty();
+        test_disp.draw_bytes(62, 31, &[0xFF, 0xFF], true);
+        assert_eq!(test_disp.get_pixel(test_disp.get_index(31, 63)), Pixel::On);
+        assert_eq!(test_disp.get_pixel(test_disp.get_index(31, 0)), Pixel::Off);
+        assert_eq!(test_disp.get_pixel(test_disp.get_index(0, 5)), Pixel::Off);
+    }
+
+    #[test]
+    fn it_wraps_sprite_origin_even_when_clipping() {
+        let mut test_disp = Display::new_empty();
+        test_disp.draw_bytes(64 + 2, 32 + 1, &[0x80], true);
+        assert_eq!(test_disp.get_pixel(test_disp.get_index(1, 2)), Pixel::On);
+    }
+
+    #[test]
+    fn it_resizes_and_clears() {
+        let mut test_disp = Display::new_empty();
+        test_disp.toggle_pixel(0);
+        test_disp.resize(Resolution::Hires);
+        assert_eq!((test_disp.width(), test_disp.height()), (128, 64));
+        let mut pixels = Vec::new();
+        test_disp.pixels_into(0, &mut pixels);
+        assert_eq!(pixels.len(), 128 * 64);
+        assert_eq!(test_disp.get_pixel(0), Pixel::Off);
+    }
+
+    #[test]
+    fn it_wraps_and_clips_at_the_right_edge_in_both_resolutions() {
+        for &resolution in &[Resolution::Lores, Resolution::Hires] {
+            let (width, _) = resolution.size();
+            let mut test_disp = Display::new_empty();
+            test_disp.resize(resolution);
+            let x = (width - 3) as u8;
+            test_disp.draw_wide_bytes(x, 0, &[0xFF, 0xFF], false);
+            for column in (width - 3..width).chain(0..13) {
+                assert_eq!(test_disp.get_pixel(column as usize), Pixel::On);
+            }
+            assert_eq!(test_disp.get_pixel(13), Pixel::Off);
+            assert_eq!(lit_pixels(&test_disp), 16);
+
+            test_disp.cls();
+            test_disp.draw_wide_bytes(x, 0, &[0xFF, 0xFF], true);
+            assert_eq!(lit_pixels(&test_disp), 3);
+        }
+    }
+
+    #[test]
+    fn it_keeps_scrolled_pixels_inside_the_screen() {
+        let mut test_disp = Display::new_empty();
+        test_disp.draw_bytes(60, 0, &[0xFF], true);
+        test_disp.scroll_right(2);
+        assert_eq!(lit_pixels(&test_disp), 2);
+        test_disp.resize(Resolution::Hires);
+        test_disp.draw_bytes(0, 63, &[0xFF], false);
+        test_disp.scroll_down(1);
+        assert_eq!(lit_pixels(&test_disp), 0);
+    }
+
+    #[test]
+    fn it_draws_wide_sprites() {
+        let mut test_disp = Display::new_empty();
+        let mut sprite = [0u8; 32];
+        sprite[0] = 0x80;
+        sprite[1] = 0x01;
+        sprite[31] = 0x01;
+        assert!(!test_disp.draw_wide_bytes(0, 0, &sprite, true));
+        assert_eq!(test_disp.get_pixel(test_disp.get_index(0, 0)), Pixel::On);
+        assert_eq!(test_disp.get_pixel(test_disp.get_index(0, 15)), Pixel::On);
+        assert_eq!(test_disp.get_pixel(test_disp.get_index(15, 15)), Pixel::On);
+        assert!(test_disp.draw_wide_bytes(0, 0, &sprite, true));
+    }
+
+    #[test]
+    fn it_scrolls_down() {
+        let mut test_disp = Display::new_empty();
+        test_disp.toggle_pixel(test_disp.get_index(0, 3));
+        test_disp.toggle_pixel(test_disp.get_index(31, 3));
+        test_disp.scroll_down(4);
+        assert_eq!(test_disp.get_pixel(test_disp.get_index(0, 3)), Pixel::Off);
+        assert_eq!(test_disp.get_pixel(test_disp.get_index(4, 3)), Pixel::On);
+        assert_eq!(lit_pixels(&test_disp), 1);
+    }
+
+    #[test]
+    fn it_scrolls_left_and_right() {
+        let mut test_disp = Display::new_empty();
+        test_disp.toggle_pixel(test_disp.get_index(2, 10));
+        test_disp.scroll_right(4);
+        assert_eq!(test_disp.get_pixel(test_disp.get_index(2, 14)), Pixel::On);
+        test_disp.scroll_left(4);
+        assert_eq!(test_disp.get_pixel(test_disp.get_index(2, 10)), Pixel::On);
+        test_disp.scroll_left(12);
+        assert_eq!(lit_pixels(&test_disp), 0);
+    }
+
+    #[test]
+    fn it_draws_into_every_selected_plane() {
+        let mut test_disp = Display::new_empty();
+        test_disp.select_planes(0b11);
+        assert!(!test_disp.draw_bytes(0, 0, &[0b1100_0000, 0b1010_0000], false));
+        assert_eq!(test_disp.get_color(0), 0b11);
+        assert_eq!(test_disp.get_color(1), 0b01);
+        assert_eq!(test_disp.get_color(2), 0b10);
+        assert_eq!(test_disp.get_color(3), 0b00);
+    }
+
+    #[test]
+    fn it_reports_collisions_on_any_plane() {
+        let mut test_disp = Display::new_empty();
+        test_disp.select_planes(0b10);
+        test_disp.draw_bytes(0, 0, &[0x80], false);
+        test_disp.select_planes(0b01);
+        assert!(!test_disp.draw_bytes(0, 0, &[0x80], false));
+        test_disp.select_planes(0b11);
+        assert!(test_disp.draw_bytes(0, 0, &[0x00, 0x80], false));
+        assert_eq!(test_disp.get_color(0), 0b01);
+    }
+
+    #[test]
+    fn it_ignores_draws_with_no_plane_selected() {
+        let mut test_disp = Display::new_empty();
+        test_disp.select_planes(0);
+        assert!(!test_disp.draw_bytes(0, 0, &[], false));
+        assert_eq!(test_disp.get_color(0), 0);
+    }
+
+    #[test]
+    fn it_clears_only_selected_planes() {
+        let mut test_disp = Display::new_empty();
+        test_disp.select_planes(0b11);
+        test_disp.draw_bytes(0, 0, &[0x80, 0x80], false);
+        test_disp.select_planes(0b10);
+        test_disp.cls();
+        assert_eq!(test_disp.get_color(0), 0b01);
+    }
+
+    #[test]
+    fn it_scrolls_only_selected_planes() {
+        let mut test_disp = Display::new_empty();
+        test_disp.select_planes(0b11);
+        test_disp.draw_bytes(0, 1, &[0x80, 0x80], false);
+        test_disp.select_planes(0b01);
+        test_disp.scroll_up(1);
+        assert_eq!(test_disp.get_color(0), 0b01);
+        assert_eq!(test_disp.get_color(64), 0b10);
+    }
+
+    fn region(x: u32, y: u32, width: u32, height: u32) -> DirtyRegion {
+        DirtyRegion {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn it_starts_dirty_everywhere() {
+        let mut test_disp = Display::new_empty();
+        assert!(test_disp.changed_since_last_frame());
+        assert_eq!(test_disp.take_dirty_regions(), [region(0, 0, 64, 32)]);
+        assert!(!test_disp.changed_since_last_frame());
+        assert!(test_disp.take_dirty_regions().is_empty());
+    }
+
+    #[test]
+    fn it_clears_changes_without_listing_them() {
+        let mut test_disp = Display::new_empty();
+        test_disp.clear_dirty();
+        assert!(!test_disp.changed_since_last_frame());
+        assert!(test_disp.take_dirty_regions().is_empty());
+    }
+
+    #[test]
+    fn it_tracks_drawn_sprites() {
+        let mut test_disp = Display::new_empty();
+        test_disp.take_dirty_regions();
+        test_disp.draw_bytes(10, 4, &[0x18, 0x24], false);
+        test_disp.draw_bytes(40, 20, &[0x80], false);
+        assert!(test_disp.changed_since_last_frame());
+        assert_eq!(
+            test_disp.take_dirty_regions(),
+            [region(12, 4, 4, 2), region(40, 20, 1, 1)]
+        );
+
+        // Erasing a pixel changes it too, and wrapped rows span the screen.
+        test_disp.draw_bytes(60, 31, &[0xFF, 0x80], false);
+        assert_eq!(
+            test_disp.take_dirty_regions(),
+            [region(60, 0, 1, 1), region(0, 31, 64, 1)]
+        );
+        test_disp.draw_bytes(60, 0, &[0x80], false);
+        assert_eq!(test_disp.take_dirty_regions(), [region(60, 0, 1, 1)]);
+    }
+
+    #[test]
+    fn it_ignores_draws_that_change_nothing() {
+        let mut test_disp = Display::new_empty();
+        test_disp.take_dirty_regions();
+        test_disp.draw_bytes(0, 0, &[0x00, 0x00], false);
+        assert!(!test_disp.changed_since_last_frame());
+    }
+
+    #[test]
+    fn it_tracks_clears_and_scrolls() {
+        let mut test_disp = Display::new_empty();
+        test_disp.draw_bytes(8, 2, &[0xF0], false);
+        test_disp.take_dirty_regions();
+
+        test_disp.cls();
+        assert_eq!(test_disp.take_dirty_regions(), [region(8, 2, 4, 1)]);
+        test_disp.cls();
+        assert!(!test_disp.changed_since_last_frame());
+
+        test_disp.draw_bytes(8, 2, &[0xF0], false);
+        test_disp.take_dirty_regions();
+        test_disp.scroll_down(3);
+        assert_eq!(
+            test_disp.take_dirty_regions(),
+            [region(8, 2, 4, 1), region(8, 5, 4, 1)]
+        );
+        test_disp.scroll_right(2);
+        assert_eq!(test_disp.take_dirty_regions(), [region(8, 5, 6, 1)]);
+    }
+
+    #[test]
+    fn it_marks_resized_displays_dirty() {
+        let mut test_disp = Display::new_empty();
+        test_disp.take_dirty_regions();
+        test_disp.resize(Resolution::Hires);
+        assert_eq!(test_disp.take_dirty_regions(), [region(0, 0, 128, 64)]);
+    }
+
+    /// Decodes `png` into its dimensions and RGBA bytes.
+    #[cfg(feature = "png")]
+    fn decode_png(png: &[u8]) -> (u32, u32, Vec<u8>) {
+        let mut reader = png::Decoder::new(png).read_info().unwrap();
+        let mut rgba = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut rgba).unwrap();
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+        (info.width, info.height, rgba)
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn it_exports_pngs_matching_the_pixels() {
+        let mut test_disp = Display::new_empty();
+        for (i, digit) in FONT_SET.chunks(5).enumerate() {
+            test_disp.draw_bytes(i as u8 * 5, i as u8, digit, false);
+        }
+
+        let palette = Palette::amber();
+        let scale = 3;
+        let (width, height, rgba) = decode_png(&test_disp.to_png(scale, &palette));
+        assert_eq!((width, height), (64 * scale, 32 * scale));
+        for y in 0..height {
+            for x in 0..width {
+                let i = ((y * width + x) * 4) as usize;
+                let pixel = test_disp.get_pixel(test_disp.get_index(y / scale, x / scale));
+                assert_eq!(
+                    rgba[i..i + 4],
+                    palette.color(pixel as u8),
+                    "at ({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn it_exports_pbms() {
+        let mut test_disp = Display::new_empty();
+        test_disp.resize(Resolution::Hires);
+        test_disp.draw_bytes(0, 0, &[0xA5], false);
+        test_disp.select_planes(0b10);
+        test_disp.draw_bytes(120, 63, &[0x01], false);
+
+        let pbm = test_disp.to_pbm();
+        let header = b"P4\n128 64\n";
+        assert_eq!(&pbm[..header.len()], header);
+        let bits = &pbm[header.len()..];
+        assert_eq!(bits.len(), 16 * 64);
+        assert_eq!(bits[0], 0xA5);
+        assert_eq!(bits[bits.len() - 1], 0x01);
+        assert_eq!(bits.iter().map(|byte| byte.count_ones()).sum::<u32>(), 5);
+    }
+
+    #[test]
+    fn it_renders_braille_cells() {
+        let mut test_disp = Display::new_empty();
+        // The digit 0 at (1, 2): rows F0 90 90 90 F0 shifted one column.
+        test_disp.draw_bytes(1, 2, &FONT_SET[..5], false);
+        test_disp.select_planes(0b10);
+        test_disp.draw_bytes(63, 31, &[0x80], false);
+
+        let text = test_disp.render_text(TextStyle::Braille);
+        let lines: Vec<Vec<char>> = text.lines().map(|line| line.chars().collect()).collect();
+        assert_eq!(lines.len(), 8);
+        assert!(lines.iter().all(|line| line.len() == 32));
+        assert_eq!(lines[0][..4], ['⢠', '⠤', '⡄', '⠀']);
+        assert_eq!(lines[1][..4], ['⠸', '⠤', '⠇', '⠀']);
+        assert_eq!(lines[7][31], '⢀');
+        assert_eq!(text.chars().filter(|&c| c != '⠀' && c != '\n').count(), 7);
+    }
+
+    #[test]
+    fn it_renders_half_blocks() {
+        let mut test_disp = Display::new_empty();
+        test_disp.draw_bytes(0, 0, &[0b1100_0000, 0b1010_0000], false);
+
+        let text = test_disp.render_text(TextStyle::HalfBlocks);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 16);
+        assert!(lines.iter().all(|line| line.chars().count() == 64));
+        assert!(lines[0].starts_with("█▀▄ "));
+        assert!(lines[1..].iter().all(|line| line.trim().is_empty()));
+    }
+
+    #[test]
+    fn it_renders_hires_text_in_half_the_lines() {
+        let mut test_disp = Display::new_empty();
+        test_disp.resize(Resolution::Hires);
+        let braille = test_disp.render_text(TextStyle::Braille);
+        assert_eq!(braille.lines().count(), 16);
+        assert!(braille.lines().all(|line| line.chars().count() == 64));
+        assert_eq!(
+            test_disp.render_text(TextStyle::Squares),
+            test_disp.render()
+        );
+    }
 }
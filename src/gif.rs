@@ -0,0 +1,271 @@
+//! Records gameplay to animated GIFs.
+
+use std::borrow::Cow;
+
+use ::gif::{Encoder, Frame, Repeat};
+use wasm_bindgen::prelude::*;
+
+use crate::chip8::{Cpu, TIMER_RATE};
+use crate::render::{render_indices, Palette};
+
+/// GIF frame delays are in hundredths of a second.
+const CENTISECONDS_PER_SECOND: u64 = 100;
+/// Browsers stretch frames shorter than this to a tenth of a second, so
+/// shorter ones are dropped and their time given to the next frame.
+const MIN_DELAY: u64 = 2;
+/// Keeps a 128-pixel-wide image within GIF's 16-bit dimensions.
+const MAX_SCALE: u32 = 64;
+
+/// Records each distinct image a machine displays into an animated GIF.
+///
+/// Pixels are stored as indices into the palette, whose colors become the
+/// GIF's global color table. Each image lasts as many 60 Hz frames as the
+/// machine's `frame_count` says it was on screen, rounded to centiseconds
+/// without drifting. The GIF keeps the size of the first image; a later
+/// change of resolution is scaled to fit it.
+#[wasm_bindgen]
+pub struct GifRecorder {
+    palette: Palette,
+    scale: u32,
+    recording: Option<Recording>,
+}
+
+struct Recording {
+    encoder: Encoder<Vec<u8>>,
+    width: u16,
+    height: u16,
+    /// `frame_count` when recording started.
+    start: u64,
+    /// `frame_count` of the latest recorded frame.
+    latest: u64,
+    /// Centiseconds of delay written so far.
+    written: u64,
+    /// Palette indices of the image on screen, which is written once the
+    /// next distinct image shows how long it lasted.
+    shown: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl GifRecorder {
+    #[wasm_bindgen(constructor)]
+    pub fn new(palette: Palette, scale: u32) -> GifRecorder {
+        GifRecorder {
+            palette,
+            scale: scale.clamp(1, MAX_SCALE),
+            recording: None,
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Starts a new recording with `cpu`'s current display as the first
+    /// image, discarding any recording in progress.
+    pub fn start(&mut self, cpu: &Cpu) {
+        let display = cpu.display();
+        let width = (display.width() * self.scale) as u16;
+        let height = (display.height() * self.scale) as u16;
+        let mut encoder = Encoder::new(Vec::new(), width, height, &self.palette.rgb())
+            .expect("encoding to memory cannot fail");
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .expect("encoding to memory cannot fail");
+
+        let mut recording = Recording {
+            encoder,
+            width,
+            height,
+            start: cpu.frame_count(),
+            latest: cpu.frame_count(),
+            written: 0,
+            shown: Vec::new(),
+        };
+        render_indices(
+            display,
+            &self.palette,
+            width as u32,
+            height as u32,
+            &mut recording.shown,
+        );
+        self.recording = Some(recording);
+    }
+
+    /// Adds `cpu`'s display if it differs from the last image. Call this
+    /// after every frame, or at least whenever the display may have changed;
+    /// it does nothing unless recording.
+    pub fn record_frame(&mut self, cpu: &Cpu) {
+        let palette = &self.palette;
+        let recording = match self.recording.as_mut() {
+            Some(recording) => recording,
+            None => return,
+        };
+        recording.latest = cpu.frame_count().max(recording.latest);
+        let mut pixels = Vec::new();
+        let (width, height) = (recording.width as u32, recording.height as u32);
+        render_indices(cpu.display(), palette, width, height, &mut pixels);
+        if pixels != recording.shown {
+            let until = recording.latest;
+            recording.write_shown(until, 0);
+            recording.shown = pixels;
+        }
+    }
+
+    /// Ends the recording, showing the last image until one frame after the
+    /// latest recorded, and returns the GIF. Returns nothing if there was no
+    /// recording.
+    pub fn stop(&mut self) -> Vec<u8> {
+        match self.recording.take() {
+            Some(mut recording) => {
+                let until = recording.latest + 1;
+                recording.write_shown(until, MIN_DELAY);
+                recording
+                    .encoder
+                    .into_inner()
+                    .expect("encoding to memory cannot fail")
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Recording {
+    /// Writes the image on screen as lasting until frame `until`, at least
+    /// `min_delay` centiseconds, unless that is too short to show.
+    fn write_shown(&mut self, until: u64, min_delay: u64) {
+        let elapsed = (until - self.start) * CENTISECONDS_PER_SECOND / TIMER_RATE as u64;
+        let delay = elapsed.saturating_sub(self.written).max(min_delay);
+        if delay < MIN_DELAY {
+            return;
+        }
+
+        let frame = Frame {
+            width: self.width,
+            height: self.height,
+            delay: delay.min(u16::MAX as u64) as u16,
+            buffer: Cow::Borrowed(&self.shown),
+            ..Frame::default()
+        };
+        self.encoder
+            .write_frame(&frame)
+            .expect("encoding to memory cannot fail");
+        self.written += delay;
+    }
+}
+
+#[cfg(test)]
+mod gif_tests {
+    use super::*;
+
+    /// Draws and erases the digit 0 at (5, 0) every five frames.
+    const BLINK_ROM: [u8; 14] = [
+        0x60, 0x05, // v0 := 5
+        0xF0, 0x15, // delay := v0
+        0xF1, 0x07, // v1 := delay
+        0x31, 0x00, // if v1 != 0
+        0x12, 0x04, //   then jump 0x204
+        0xD0, 0x15, // sprite v0 v1 5
+        0x12, 0x00, // jump 0x200
+    ];
+
+    struct Decoded {
+        palette: Vec<u8>,
+        frames: Vec<(u16, Vec<u8>)>,
+        width: u16,
+    }
+
+    fn decode(gif: &[u8]) -> Decoded {
+        let mut options = ::gif::DecodeOptions::new();
+        options.set_color_output(::gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(gif).unwrap();
+        let palette = decoder.global_palette().unwrap().to_vec();
+        let width = decoder.width();
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.delay, frame.buffer.to_vec()));
+        }
+        Decoded {
+            palette,
+            frames,
+            width,
+        }
+    }
+
+    fn blinking_cpu() -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&BLINK_ROM).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn it_records_distinct_frames_with_frame_clock_delays() {
+        let mut cpu = blinking_cpu();
+        let mut recorder = GifRecorder::new(Palette::amber(), 2);
+        recorder.start(&cpu);
+        for _ in 0..60 {
+            cpu.run_frame().unwrap();
+            recorder.record_frame(&cpu);
+        }
+        assert!(recorder.is_recording());
+        let gif = decode(&recorder.stop());
+        assert!(!recorder.is_recording());
+
+        assert_eq!(gif.width, 128);
+        assert!((10..=14).contains(&gif.frames.len()));
+        let delays: Vec<u16> = gif.frames.iter().map(|&(delay, _)| delay).collect();
+        assert!(delays.iter().all(|&delay| delay >= MIN_DELAY as u16));
+        // 61 frames on screen, counting the last one.
+        assert_eq!(delays.iter().map(|&delay| delay as u32).sum::<u32>(), 101);
+        for pair in gif.frames.windows(2) {
+            assert_ne!(pair[0].1, pair[1].1);
+        }
+    }
+
+    #[test]
+    fn it_writes_palette_indices() {
+        let mut cpu = blinking_cpu();
+        let palette = Palette::lcd();
+        let mut recorder = GifRecorder::new(palette.clone(), 1);
+        recorder.start(&cpu);
+        let mut pixels = Vec::new();
+        loop {
+            cpu.display().pixels_into(0, &mut pixels);
+            if pixels.contains(&1) {
+                break;
+            }
+            cpu.run_frame().unwrap();
+        }
+        recorder.record_frame(&cpu);
+        let gif = decode(&recorder.stop());
+
+        assert_eq!(gif.palette, palette.rgb());
+        let (_, pixels) = &gif.frames[gif.frames.len() - 1];
+        for (i, &index) in pixels.iter().enumerate() {
+            assert_eq!(index, palette.index(cpu.display().get_color(i)));
+        }
+        assert!(pixels.contains(&1));
+    }
+
+    #[test]
+    fn it_keeps_the_first_size_across_resolution_changes() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[0x00, 0xFF, 0x12, 0x02]).unwrap();
+        let mut recorder = GifRecorder::new(Palette::monochrome(), 4);
+        recorder.start(&cpu);
+        cpu.run_frame().unwrap();
+        recorder.record_frame(&cpu);
+        let gif = decode(&recorder.stop());
+        assert_eq!(gif.width, 256);
+        assert!(gif
+            .frames
+            .iter()
+            .all(|(_, pixels)| pixels.len() == 256 * 128));
+    }
+
+    #[test]
+    fn it_does_nothing_unless_recording() {
+        let mut recorder = GifRecorder::new(Palette::monochrome(), 1);
+        recorder.record_frame(&Cpu::new());
+        assert!(recorder.stop().is_empty());
+    }
+}
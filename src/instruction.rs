@@ -0,0 +1,389 @@
+use std::fmt;
+
+/// A decoded CHIP-8, SUPER-CHIP or XO-CHIP instruction.
+///
+/// Operands use the usual names: `x` and `y` are register indices, `n` is a
+/// 4-bit immediate, `kk` an 8-bit immediate and `nnn` a 12-bit address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    /// 00CN: scroll the display down n rows.
+    ScrollDown { n: u8 },
+    /// 00DN: scroll the display up n rows.
+    ScrollUp { n: u8 },
+    /// 00E0
+    Cls,
+    /// 00EE
+    Ret,
+    /// 00FB: scroll the display right 4 pixels.
+    ScrollRight,
+    /// 00FC: scroll the display left 4 pixels.
+    ScrollLeft,
+    /// 00FD
+    Exit,
+    /// 00FE
+    Lores,
+    /// 00FF
+    Hires,
+    /// 1nnn
+    Jump { nnn: u16 },
+    /// 2nnn
+    Call { nnn: u16 },
+    /// 3xkk
+    SkipIfEqual { x: usize, kk: u8 },
+    /// 4xkk
+    SkipIfNotEqual { x: usize, kk: u8 },
+    /// 5xy0
+    SkipIfRegistersEqual { x: usize, y: usize },
+    /// 5xy2
+    StoreRegisterRange { x: usize, y: usize },
+    /// 5xy3
+    LoadRegisterRange { x: usize, y: usize },
+    /// 6xkk
+    Load { x: usize, kk: u8 },
+    /// 7xkk
+    Add { x: usize, kk: u8 },
+    /// 8xy0
+    Copy { x: usize, y: usize },
+    /// 8xy1
+    Or { x: usize, y: usize },
+    /// 8xy2
+    And { x: usize, y: usize },
+    /// 8xy3
+    Xor { x: usize, y: usize },
+    /// 8xy4
+    AddRegisters { x: usize, y: usize },
+    /// 8xy5
+    Sub { x: usize, y: usize },
+    /// 8xy6
+    ShiftRight { x: usize, y: usize },
+    /// 8xy7
+    SubReversed { x: usize, y: usize },
+    /// 8xyE
+    ShiftLeft { x: usize, y: usize },
+    /// 9xy0
+    SkipIfRegistersNotEqual { x: usize, y: usize },
+    /// Annn
+    LoadI { nnn: u16 },
+    /// Bnnn
+    JumpWithOffset { nnn: u16 },
+    /// Cxkk
+    Random { x: usize, kk: u8 },
+    /// Dxyn
+    Draw { x: usize, y: usize, n: u8 },
+    /// Ex9E
+    SkipIfKeyPressed { x: usize },
+    /// ExA1
+    SkipIfKeyNotPressed { x: usize },
+    /// F000 NNNN: the address is in the word following the instruction.
+    LoadLongI,
+    /// Fn01
+    SelectPlanes { n: u8 },
+    /// F002: load the 16-byte audio pattern at I.
+    LoadAudioPattern,
+    /// Fx07
+    LoadDelayTimer { x: usize },
+    /// Fx0A
+    WaitForKey { x: usize },
+    /// Fx15
+    SetDelayTimer { x: usize },
+    /// Fx18
+    SetSoundTimer { x: usize },
+    /// Fx1E
+    AddToI { x: usize },
+    /// Fx29
+    LoadFont { x: usize },
+    /// Fx30
+    LoadBigFont { x: usize },
+    /// Fx3A: set the audio pattern playback pitch.
+    SetPitch { x: usize },
+    /// Fx33
+    StoreBcd { x: usize },
+    /// Fx55
+    StoreRegisters { x: usize },
+    /// Fx65
+    LoadRegisters { x: usize },
+    /// Fx75
+    StoreFlags { x: usize },
+    /// Fx85
+    LoadFlags { x: usize },
+    /// Any opcode that is not one of the above.
+    Unknown(u16),
+}
+
+/// Decodes a single 16-bit opcode.
+pub fn decode(opcode: u16) -> Instruction {
+    use Instruction::*;
+
+    let nibbles = get_nibbles(opcode);
+    let nnn = opcode & 0x0FFF;
+    let kk = (opcode & 0x00FF) as u8;
+
+    match nibbles {
+        (0x0, 0, 0xC, n) => ScrollDown { n },
+        (0x0, 0, 0xD, n) => ScrollUp { n },
+        (0x0, 0, 0xE, 0) => Cls,
+        (0x0, 0, 0xE, 0xE) => Ret,
+        (0x0, 0, 0xF, 0xB) => ScrollRight,
+        (0x0, 0, 0xF, 0xC) => ScrollLeft,
+        (0x0, 0, 0xF, 0xD) => Exit,
+        (0x0, 0, 0xF, 0xE) => Lores,
+        (0x0, 0, 0xF, 0xF) => Hires,
+        (0x1, _, _, _) => Jump { nnn },
+        (0x2, _, _, _) => Call { nnn },
+        (0x3, x, _, _) => SkipIfEqual { x, kk },
+        (0x4, x, _, _) => SkipIfNotEqual { x, kk },
+        (0x5, x, y, 0) => SkipIfRegistersEqual { x, y },
+        (0x5, x, y, 2) => StoreRegisterRange { x, y },
+        (0x5, x, y, 3) => LoadRegisterRange { x, y },
+        (0x6, x, _, _) => Load { x, kk },
+        (0x7, x, _, _) => Add { x, kk },
+        (0x8, x, y, 0) => Copy { x, y },
+        (0x8, x, y, 1) => Or { x, y },
+        (0x8, x, y, 2) => And { x, y },
+        (0x8, x, y, 3) => Xor { x, y },
+        (0x8, x, y, 4) => AddRegisters { x, y },
+        (0x8, x, y, 5) => Sub { x, y },
+        (0x8, x, y, 6) => ShiftRight { x, y },
+        (0x8, x, y, 7) => SubReversed { x, y },
+        (0x8, x, y, 0xE) => ShiftLeft { x, y },
+        (0x9, x, y, 0) => SkipIfRegistersNotEqual { x, y },
+        (0xA, _, _, _) => LoadI { nnn },
+        (0xB, _, _, _) => JumpWithOffset { nnn },
+        (0xC, x, _, _) => Random { x, kk },
+        (0xD, x, y, n) => Draw { x, y, n },
+        (0xE, x, 9, 0xE) => SkipIfKeyPressed { x },
+        (0xE, x, 0xA, 1) => SkipIfKeyNotPressed { x },
+        (0xF, 0, 0, 0) => LoadLongI,
+        (0xF, n, 0, 1) => SelectPlanes { n: n as u8 },
+        (0xF, 0, 0, 2) => LoadAudioPattern,
+        (0xF, x, 0, 7) => LoadDelayTimer { x },
+        (0xF, x, 0, 0xA) => WaitForKey { x },
+        (0xF, x, 1, 5) => SetDelayTimer { x },
+        (0xF, x, 1, 8) => SetSoundTimer { x },
+        (0xF, x, 1, 0xE) => AddToI { x },
+        (0xF, x, 2, 9) => LoadFont { x },
+        (0xF, x, 3, 0) => LoadBigFont { x },
+        (0xF, x, 3, 3) => StoreBcd { x },
+        (0xF, x, 3, 0xA) => SetPitch { x },
+        (0xF, x, 5, 5) => StoreRegisters { x },
+        (0xF, x, 6, 5) => LoadRegisters { x },
+        (0xF, x, 7, 5) => StoreFlags { x },
+        (0xF, x, 8, 5) => LoadFlags { x },
+        _ => Unknown(opcode),
+    }
+}
+
+impl Instruction {
+    /// Encodes the instruction back into its 16-bit opcode.
+    pub fn encode(&self) -> u16 {
+        use Instruction::*;
+
+        match *self {
+            ScrollDown { n } => 0x00C0 | n as u16,
+            ScrollUp { n } => 0x00D0 | n as u16,
+            Cls => 0x00E0,
+            Ret => 0x00EE,
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            Lores => 0x00FE,
+            Hires => 0x00FF,
+            Jump { nnn } => 0x1000 | nnn,
+            Call { nnn } => 0x2000 | nnn,
+            SkipIfEqual { x, kk } => with_x_kk(0x3, x, kk),
+            SkipIfNotEqual { x, kk } => with_x_kk(0x4, x, kk),
+            SkipIfRegistersEqual { x, y } => with_x_y(0x5, x, y, 0),
+            StoreRegisterRange { x, y } => with_x_y(0x5, x, y, 2),
+            LoadRegisterRange { x, y } => with_x_y(0x5, x, y, 3),
+            Load { x, kk } => with_x_kk(0x6, x, kk),
+            Add { x, kk } => with_x_kk(0x7, x, kk),
+            Copy { x, y } => with_x_y(0x8, x, y, 0),
+            Or { x, y } => with_x_y(0x8, x, y, 1),
+            And { x, y } => with_x_y(0x8, x, y, 2),
+            Xor { x, y } => with_x_y(0x8, x, y, 3),
+            AddRegisters { x, y } => with_x_y(0x8, x, y, 4),
+            Sub { x, y } => with_x_y(0x8, x, y, 5),
+            ShiftRight { x, y } => with_x_y(0x8, x, y, 6),
+            SubReversed { x, y } => with_x_y(0x8, x, y, 7),
+            ShiftLeft { x, y } => with_x_y(0x8, x, y, 0xE),
+            SkipIfRegistersNotEqual { x, y } => with_x_y(0x9, x, y, 0),
+            LoadI { nnn } => 0xA000 | nnn,
+            JumpWithOffset { nnn } => 0xB000 | nnn,
+            Random { x, kk } => with_x_kk(0xC, x, kk),
+            Draw { x, y, n } => with_x_y(0xD, x, y, n),
+            SkipIfKeyPressed { x } => with_x_kk(0xE, x, 0x9E),
+            SkipIfKeyNotPressed { x } => with_x_kk(0xE, x, 0xA1),
+            LoadLongI => 0xF000,
+            SelectPlanes { n } => with_x_kk(0xF, n as usize, 0x01),
+            LoadAudioPattern => 0xF002,
+            LoadDelayTimer { x } => with_x_kk(0xF, x, 0x07),
+            WaitForKey { x } => with_x_kk(0xF, x, 0x0A),
+            SetDelayTimer { x } => with_x_kk(0xF, x, 0x15),
+            SetSoundTimer { x } => with_x_kk(0xF, x, 0x18),
+            AddToI { x } => with_x_kk(0xF, x, 0x1E),
+            LoadFont { x } => with_x_kk(0xF, x, 0x29),
+            LoadBigFont { x } => with_x_kk(0xF, x, 0x30),
+            StoreBcd { x } => with_x_kk(0xF, x, 0x33),
+            SetPitch { x } => with_x_kk(0xF, x, 0x3A),
+            StoreRegisters { x } => with_x_kk(0xF, x, 0x55),
+            LoadRegisters { x } => with_x_kk(0xF, x, 0x65),
+            StoreFlags { x } => with_x_kk(0xF, x, 0x75),
+            LoadFlags { x } => with_x_kk(0xF, x, 0x85),
+            Unknown(opcode) => opcode,
+        }
+    }
+}
+
+/// Formats the instruction in Cowgod-style assembly, e.g. `LD V3, 0x1F`.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instruction::*;
+
+        match *self {
+            ScrollDown { n } => write!(f, "SCD {}", n),
+            ScrollUp { n } => write!(f, "SCU {}", n),
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            ScrollRight => write!(f, "SCR"),
+            ScrollLeft => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Lores => write!(f, "LOW"),
+            Hires => write!(f, "HIGH"),
+            Jump { nnn } => write!(f, "JP {:#05X}", nnn),
+            Call { nnn } => write!(f, "CALL {:#05X}", nnn),
+            SkipIfEqual { x, kk } => write!(f, "SE V{:X}, {:#04X}", x, kk),
+            SkipIfNotEqual { x, kk } => write!(f, "SNE V{:X}, {:#04X}", x, kk),
+            SkipIfRegistersEqual { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            StoreRegisterRange { x, y } => write!(f, "SAVE V{:X} - V{:X}", x, y),
+            LoadRegisterRange { x, y } => write!(f, "LOAD V{:X} - V{:X}", x, y),
+            Load { x, kk } => write!(f, "LD V{:X}, {:#04X}", x, kk),
+            Add { x, kk } => write!(f, "ADD V{:X}, {:#04X}", x, kk),
+            Copy { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddRegisters { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            SubReversed { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SkipIfRegistersNotEqual { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LoadI { nnn } => write!(f, "LD I, {:#05X}", nnn),
+            JumpWithOffset { nnn } => write!(f, "JP V0, {:#05X}", nnn),
+            Random { x, kk } => write!(f, "RND V{:X}, {:#04X}", x, kk),
+            Draw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            SkipIfKeyPressed { x } => write!(f, "SKP V{:X}", x),
+            SkipIfKeyNotPressed { x } => write!(f, "SKNP V{:X}", x),
+            LoadLongI => write!(f, "LD I, LONG"),
+            SelectPlanes { n } => write!(f, "PLANE {}", n),
+            LoadAudioPattern => write!(f, "AUDIO"),
+            LoadDelayTimer { x } => write!(f, "LD V{:X}, DT", x),
+            WaitForKey { x } => write!(f, "LD V{:X}, K", x),
+            SetDelayTimer { x } => write!(f, "LD DT, V{:X}", x),
+            SetSoundTimer { x } => write!(f, "LD ST, V{:X}", x),
+            AddToI { x } => write!(f, "ADD I, V{:X}", x),
+            LoadFont { x } => write!(f, "LD F, V{:X}", x),
+            LoadBigFont { x } => write!(f, "LD HF, V{:X}", x),
+            StoreBcd { x } => write!(f, "LD B, V{:X}", x),
+            SetPitch { x } => write!(f, "PITCH V{:X}", x),
+            StoreRegisters { x } => write!(f, "LD [I], V{:X}", x),
+            LoadRegisters { x } => write!(f, "LD V{:X}, [I]", x),
+            StoreFlags { x } => write!(f, "LD R, V{:X}", x),
+            LoadFlags { x } => write!(f, "LD V{:X}, R", x),
+            Unknown(opcode) => write!(f, "DW {:#06X}", opcode),
+        }
+    }
+}
+
+fn with_x_kk(high: u16, x: usize, kk: u8) -> u16 {
+    high << 12 | (x as u16 & 0xF) << 8 | kk as u16
+}
+
+fn with_x_y(high: u16, x: usize, y: usize, n: u8) -> u16 {
+    high << 12 | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4 | (n as u16 & 0xF)
+}
+
+fn get_nibbles(opcode: u16) -> (u8, usize, usize, u8) {
+    (
+        ((opcode & 0xF000) >> 12) as u8,
+        ((opcode & 0x0F00) >> 8) as usize,
+        ((opcode & 0x00F0) >> 4) as usize,
+        (opcode & 0x000F) as u8,
+    )
+}
+
+#[cfg(test)]
+mod instruction_tests {
+    use super::*;
+
+    #[test]
+    fn it_splits_nibbles() {
+        let nibbles = get_nibbles(0x1234);
+        let expected_nibbles: (u8, usize, usize, u8) = (1, 2, 3, 4);
+        assert_eq!(expected_nibbles, nibbles);
+    }
+
+    #[test]
+    fn it_round_trips_every_opcode() {
+        for opcode in 0..=0xFFFF {
+            assert_eq!(decode(opcode).encode(), opcode, "{:#06X}", opcode);
+        }
+    }
+
+    #[test]
+    fn it_decodes_unknown_opcodes() {
+        let unknown = (0..=0xFFFF)
+            .filter(|&opcode| decode(opcode) == Instruction::Unknown(opcode))
+            .count();
+        let expected = (0x1000 - 39) // 0nnn, bar the 39 SUPER-CHIP/XO-CHIP forms
+            + 0x100 * 13 // 5xyN with N other than 0, 2 or 3
+            + 0x100 * 7 // 8xyN with N in 8..=D or F
+            + 0x100 * 15 // 9xyN with N other than 0
+            + 0x10 * (0x100 - 2) // ExNN other than 9E and A1
+            + 0x10 * (0x100 - 14)
+            - 2; // FxNN other than the 14 known, F000 and F002
+        assert_eq!(unknown, expected);
+    }
+
+    #[test]
+    fn it_decodes_named_operands() {
+        assert_eq!(decode(0x00E0), Instruction::Cls);
+        assert_eq!(decode(0x1ABC), Instruction::Jump { nnn: 0xABC });
+        assert_eq!(
+            decode(0x3A42),
+            Instruction::SkipIfEqual { x: 0xA, kk: 0x42 }
+        );
+        assert_eq!(decode(0x8AB4), Instruction::AddRegisters { x: 0xA, y: 0xB });
+        assert_eq!(decode(0xD125), Instruction::Draw { x: 1, y: 2, n: 5 });
+        assert_eq!(decode(0xF000), Instruction::LoadLongI);
+        assert_eq!(decode(0xF301), Instruction::SelectPlanes { n: 3 });
+        assert_eq!(decode(0xF002), Instruction::LoadAudioPattern);
+        assert_eq!(decode(0xF102), Instruction::Unknown(0xF102));
+        assert_eq!(decode(0xF53A), Instruction::SetPitch { x: 5 });
+        assert_eq!(decode(0xF165), Instruction::LoadRegisters { x: 1 });
+        assert_eq!(decode(0x0123), Instruction::Unknown(0x0123));
+        assert_eq!(decode(0x5AB1), Instruction::Unknown(0x5AB1));
+    }
+
+    #[test]
+    fn it_encodes_named_operands() {
+        assert_eq!(Instruction::ScrollDown { n: 7 }.encode(), 0x00C7);
+        assert_eq!(Instruction::Call { nnn: 0x345 }.encode(), 0x2345);
+        assert_eq!(Instruction::Random { x: 3, kk: 0x0F }.encode(), 0xC30F);
+        assert_eq!(Instruction::SkipIfKeyNotPressed { x: 9 }.encode(), 0xE9A1);
+        assert_eq!(Instruction::LoadBigFont { x: 2 }.encode(), 0xF230);
+    }
+
+    #[test]
+    fn it_formats_mnemonics() {
+        assert_eq!(decode(0x631F).to_string(), "LD V3, 0x1F");
+        assert_eq!(decode(0xD015).to_string(), "DRW V0, V1, 5");
+        assert_eq!(decode(0x2ABC).to_string(), "CALL 0xABC");
+        assert_eq!(decode(0x00C4).to_string(), "SCD 4");
+        assert_eq!(decode(0x8AB6).to_string(), "SHR VA, VB");
+        assert_eq!(decode(0xFA65).to_string(), "LD VA, [I]");
+        assert_eq!(decode(0xF002).to_string(), "AUDIO");
+        assert_eq!(decode(0xF23A).to_string(), "PITCH V2");
+        assert_eq!(decode(0x0123).to_string(), "DW 0x0123");
+    }
+}
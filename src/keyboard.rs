@@ -1,36 +1,250 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
 use wasm_bindgen::prelude::*;
 
+use crate::state::{SaveStateError, StateReader, StateWriter};
+
+pub const KEY_COUNT: usize = 16;
+
+/// A key index outside the hex keypad's 0-F.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidKey(pub u8);
+
+impl fmt::Display for InvalidKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no such key {:#04X}, keys are 0x0-0xF", self.0)
+    }
+}
+
+impl Error for InvalidKey {}
+
+impl From<InvalidKey> for JsValue {
+    fn from(err: InvalidKey) -> JsValue {
+        JsValue::from_str(&err.to_string())
+    }
+}
+
 #[wasm_bindgen]
 #[derive(Debug)]
 pub struct Keyboard {
-    keys: [u8; 16],
+    keys: [u8; KEY_COUNT],
 }
 
 impl Keyboard {
     pub fn new() -> Keyboard {
-        Keyboard { keys: [0; 16] }
+        Keyboard {
+            keys: [0; KEY_COUNT],
+        }
     }
 
     pub fn reset_keys(&mut self) {
-        for i in 0..16 {
-            self.keys[i] = 0;
-        }        
+        self.keys = [0; KEY_COUNT];
     }
 
     pub fn get_internal_array(&self) -> *const u8 {
         self.keys.as_ptr()
     }
 
-    pub fn set_key(&mut self, key: u8) {
-        self.keys[key as usize] = 1;
+    pub fn set_key(&mut self, key: u8) -> Result<(), InvalidKey> {
+        *self.key_mut(key)? = 1;
+        Ok(())
     }
 
+    pub fn release_key(&mut self, key: u8) -> Result<(), InvalidKey> {
+        *self.key_mut(key)? = 0;
+        Ok(())
+    }
+
+    /// Whether `key` is held down; there is never a key above 0xF to hold.
     pub fn key_is_pressed(&self, key: u8) -> bool {
-        self.keys[key as usize] != 0
+        self.keys.get(key as usize).is_some_and(|&state| state != 0)
+    }
+
+    /// The lowest key held down, if any.
+    pub fn first_pressed(&self) -> Option<u8> {
+        (0..KEY_COUNT as u8).find(|&key| self.key_is_pressed(key))
+    }
+
+    fn key_mut(&mut self, key: u8) -> Result<&mut u8, InvalidKey> {
+        self.keys.get_mut(key as usize).ok_or(InvalidKey(key))
+    }
+}
+
+impl Keyboard {
+    pub(crate) fn save(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.keys);
+    }
+
+    pub(crate) fn load(reader: &mut StateReader) -> Result<Keyboard, SaveStateError> {
+        let mut keys = [0; KEY_COUNT];
+        keys.copy_from_slice(reader.bytes(KEY_COUNT)?);
+        Ok(Keyboard { keys })
+    }
+}
+
+/// A key going down or up, timed in instructions from the start of the next
+/// `Cpu::run_frame` or `Cpu::advance` call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub cycle: u32,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// Key events waiting for the instruction boundary they belong to.
+#[derive(Debug, Default)]
+pub(crate) struct InputQueue {
+    events: VecDeque<KeyEvent>,
+}
+
+impl InputQueue {
+    /// Queues `event` after any others for the same cycle.
+    pub(crate) fn push(&mut self, event: KeyEvent) -> Result<(), InvalidKey> {
+        if event.key as usize >= KEY_COUNT {
+            return Err(InvalidKey(event.key));
+        }
+        let index = self
+            .events
+            .partition_point(|queued| queued.cycle <= event.cycle);
+        self.events.insert(index, event);
+        Ok(())
+    }
+
+    /// Applies the events due by `cycle` to `keyboard`. A release of a key
+    /// pressed at this same boundary is held back one instruction, so even
+    /// the shortest tap is seen by the program. Later events for that key
+    /// wait behind it; other keys are unaffected.
+    pub(crate) fn apply_due(&mut self, cycle: u32, keyboard: &mut Keyboard) {
+        let mut pressed_here = 0u16;
+        let mut held = 0u16;
+        self.events.retain(|event| {
+            let bit = 1 << event.key;
+            if event.cycle > cycle {
+                return true;
+            }
+            if held & bit != 0 || (!event.pressed && pressed_here & bit != 0) {
+                held |= bit;
+                return true;
+            }
+            keyboard.keys[event.key as usize] = event.pressed as u8;
+            if event.pressed {
+                pressed_here |= bit;
+            }
+            false
+        });
+    }
+
+    /// Makes the remaining events relative to the next call, `cycles`
+    /// instructions later. Overdue events stay first in line.
+    pub(crate) fn rebase(&mut self, cycles: u32) {
+        for event in &mut self.events {
+            event.cycle = event.cycle.saturating_sub(cycles);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.events.clear();
+    }
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Keyboard::new()
     }
 }
 
 #[cfg(test)]
 mod keyboard_tests {
-    use super::Keyboard;
+    use super::{InputQueue, InvalidKey, KeyEvent, Keyboard};
+
+    #[test]
+    fn it_sets_and_resets_keys() {
+        let mut keyboard = Keyboard::new();
+        keyboard.set_key(0xA).unwrap();
+        assert!(keyboard.key_is_pressed(0xA));
+        keyboard.reset_keys();
+        assert!(!keyboard.key_is_pressed(0xA));
+    }
+
+    #[test]
+    fn it_releases_keys() {
+        let mut keyboard = Keyboard::new();
+        keyboard.set_key(0x3).unwrap();
+        keyboard.set_key(0x9).unwrap();
+        assert_eq!(keyboard.first_pressed(), Some(0x3));
+        keyboard.release_key(0x3).unwrap();
+        assert!(!keyboard.key_is_pressed(0x3));
+        assert_eq!(keyboard.first_pressed(), Some(0x9));
+    }
+
+    #[test]
+    fn it_rejects_keys_above_f() {
+        let mut keyboard = Keyboard::new();
+        assert_eq!(keyboard.set_key(0x10), Err(InvalidKey(0x10)));
+        assert_eq!(keyboard.release_key(0xFF), Err(InvalidKey(0xFF)));
+        assert!(!keyboard.key_is_pressed(0x10));
+        assert_eq!(keyboard.first_pressed(), None);
+    }
+
+    fn event(cycle: u32, key: u8, pressed: bool) -> KeyEvent {
+        KeyEvent {
+            cycle,
+            key,
+            pressed,
+        }
+    }
+
+    #[test]
+    fn it_applies_queued_events_in_cycle_order() {
+        let mut queue = InputQueue::default();
+        let mut keyboard = Keyboard::new();
+        queue.push(event(5, 0x1, false)).unwrap();
+        queue.push(event(2, 0x1, true)).unwrap();
+
+        queue.apply_due(1, &mut keyboard);
+        assert!(!keyboard.key_is_pressed(0x1));
+        queue.apply_due(2, &mut keyboard);
+        assert!(keyboard.key_is_pressed(0x1));
+        queue.rebase(3);
+        queue.apply_due(2, &mut keyboard);
+        assert!(!keyboard.key_is_pressed(0x1));
+    }
+
+    #[test]
+    fn it_holds_taps_for_one_boundary() {
+        let mut queue = InputQueue::default();
+        let mut keyboard = Keyboard::new();
+        queue.push(event(0, 0x2, true)).unwrap();
+        queue.push(event(0, 0x2, false)).unwrap();
+
+        queue.apply_due(3, &mut keyboard);
+        assert!(keyboard.key_is_pressed(0x2));
+        queue.apply_due(4, &mut keyboard);
+        assert!(!keyboard.key_is_pressed(0x2));
+        assert_eq!(queue.push(event(0, 0x10, true)), Err(InvalidKey(0x10)));
+    }
+
+    #[test]
+    fn it_applies_other_keys_behind_a_held_tap() {
+        let mut queue = InputQueue::default();
+        let mut keyboard = Keyboard::new();
+        queue.push(event(0, 0x2, true)).unwrap();
+        queue.push(event(0, 0x2, false)).unwrap();
+        queue.push(event(0, 0x3, true)).unwrap();
+        queue.push(event(0, 0x2, true)).unwrap();
+        queue.push(event(0, 0x4, true)).unwrap();
+        queue.push(event(0, 0x4, false)).unwrap();
+
+        queue.apply_due(0, &mut keyboard);
+        assert!(keyboard.key_is_pressed(0x2));
+        assert!(keyboard.key_is_pressed(0x3));
+        assert!(keyboard.key_is_pressed(0x4));
+
+        // The second press of 0x2 waited behind its release.
+        queue.apply_due(1, &mut keyboard);
+        assert!(keyboard.key_is_pressed(0x2));
+        assert!(keyboard.key_is_pressed(0x3));
+        assert!(!keyboard.key_is_pressed(0x4));
+    }
 }
@@ -1,13 +1,25 @@
 mod utils;
 
-use wasm_bindgen::prelude::*;
-
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
 // allocator.
 #[cfg(feature = "wee_alloc")]
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
+pub mod asm;
+pub mod audio;
 pub mod chip8;
+pub mod disasm;
 pub mod display;
+#[cfg(feature = "gif")]
+pub mod gif;
+pub mod instruction;
 pub mod keyboard;
+pub mod phosphor;
+pub mod quirks;
+pub mod random;
+pub mod render;
+pub mod rewind;
+pub mod state;
+pub mod wav;
+pub mod y4m;
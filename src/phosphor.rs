@@ -0,0 +1,302 @@
+//! Post-processing that hides the flicker of XOR-drawn sprites.
+//!
+//! CHIP-8 games move a sprite by erasing it and drawing it again, so on a
+//! display that shows every frame exactly, moving objects blink. A CRT's
+//! phosphor glow hid that. `Phosphor` brings it back: either pixels fade out
+//! over a number of frames after going dark, or each image is averaged with
+//! the one before it.
+
+use wasm_bindgen::prelude::*;
+
+use crate::chip8::Cpu;
+use crate::display::Display;
+use crate::render::{fill_scaled, Palette};
+
+const FULL: u32 = 0xFF;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    /// Pixels fade linearly to dark over this many frames.
+    Decay(u8),
+    /// Each pixel is the average of the last two frames.
+    Blend,
+}
+
+/// Turns a machine's display into per-pixel intensities that persist
+/// across frames, as a grayscale buffer and as an RGBA8 image.
+///
+/// Call `update` once per frame. Time is taken from `Cpu::frame_count`, so
+/// frames run between two updates still age the image.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct Phosphor {
+    mode: Mode,
+    palette: Palette,
+    scale: u32,
+    width: u32,
+    height: u32,
+    /// `frame_count` at the last update.
+    frame: Option<u64>,
+    /// Frames since each pixel was last lit, saturating; 0 if lit now.
+    age: Vec<u8>,
+    /// Whether each pixel was lit at the update before the last.
+    previous: Vec<bool>,
+    /// The last color index each pixel was lit with.
+    color: Vec<u8>,
+    intensity: Vec<u8>,
+    rgba: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl Phosphor {
+    /// Pixels that go dark fade out over `frames` frames; 0 or 1 turns the
+    /// effect off.
+    pub fn decay(frames: u8, palette: Palette, scale: u32) -> Phosphor {
+        Phosphor::new(Mode::Decay(frames.max(1)), palette, scale)
+    }
+
+    /// Every image is averaged with the one before, so a sprite that is
+    /// erased and redrawn on alternate frames shows at half brightness.
+    pub fn blend(palette: Palette, scale: u32) -> Phosphor {
+        Phosphor::new(Mode::Blend, palette, scale)
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn set_scale(&mut self, scale: u32) {
+        self.scale = scale.max(1);
+    }
+
+    /// Takes in the display as it is now and recomputes the intensities
+    /// and the RGBA image. A change of resolution starts over.
+    pub fn update(&mut self, cpu: &Cpu) {
+        let display = cpu.display();
+        let size = (display.width(), display.height());
+        if size != (self.width, self.height) {
+            self.start_over(display);
+        }
+
+        let frame = cpu.frame_count();
+        let elapsed = match self.frame {
+            Some(last) => frame.saturating_sub(last).min(u8::MAX as u64) as u8,
+            None => u8::MAX,
+        };
+        self.frame = Some(frame);
+
+        for i in 0..self.age.len() {
+            let color = display.get_color(i);
+            let lit_before = self.age[i] == 0;
+            if elapsed > 0 {
+                self.previous[i] = lit_before;
+            }
+            if color != 0 {
+                self.age[i] = 0;
+                self.color[i] = color;
+            } else {
+                self.age[i] = self.age[i].saturating_add(elapsed.max(lit_before as u8));
+            }
+            self.intensity[i] = self.brightness(i);
+        }
+
+        let (palette, intensity, color) = (&self.palette, &self.intensity, &self.color);
+        fill_scaled(self.width, self.height, self.scale, &mut self.rgba, |i| {
+            mix(palette.color(0), palette.color(color[i]), intensity[i])
+        });
+    }
+
+    /// Width of the RGBA image in pixels.
+    pub fn width(&self) -> u32 {
+        self.width * self.scale
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height * self.scale
+    }
+
+    /// Pointer to one intensity byte per display pixel, 0 for dark and 255
+    /// for fully lit, in wasm memory.
+    pub fn intensity_ptr(&self) -> *const u8 {
+        self.intensity.as_ptr()
+    }
+
+    /// Pointer to `width * height * 4` bytes of RGBA in wasm memory.
+    pub fn rgba_ptr(&self) -> *const u8 {
+        self.rgba.as_ptr()
+    }
+}
+
+impl Phosphor {
+    fn new(mode: Mode, palette: Palette, scale: u32) -> Phosphor {
+        Phosphor {
+            mode,
+            palette,
+            scale: scale.max(1),
+            width: 0,
+            height: 0,
+            frame: None,
+            age: Vec::new(),
+            previous: Vec::new(),
+            color: Vec::new(),
+            intensity: Vec::new(),
+            rgba: Vec::new(),
+        }
+    }
+
+    /// Display-sized grayscale intensities from the last `update`.
+    pub fn intensity(&self) -> &[u8] {
+        &self.intensity
+    }
+
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+
+    fn start_over(&mut self, display: &Display) {
+        self.width = display.width();
+        self.height = display.height();
+        let size = (self.width * self.height) as usize;
+        self.frame = None;
+        self.age = vec![u8::MAX; size];
+        self.previous = vec![false; size];
+        self.color = vec![1; size];
+        self.intensity = vec![0; size];
+    }
+
+    fn brightness(&self, i: usize) -> u8 {
+        let age = self.age[i] as u32;
+        match self.mode {
+            Mode::Decay(frames) => {
+                let frames = frames as u32;
+                (FULL * frames.saturating_sub(age) / frames) as u8
+            }
+            Mode::Blend => {
+                let lit = (age == 0) as u32 + self.previous[i] as u32;
+                (FULL * lit).div_ceil(2) as u8
+            }
+        }
+    }
+}
+
+/// `on` over `off` at `intensity` out of 255.
+fn mix(off: [u8; 4], on: [u8; 4], intensity: u8) -> [u8; 4] {
+    let mut color = off;
+    for (channel, &target) in color.iter_mut().zip(&on) {
+        let (from, to) = (*channel as u32, target as u32);
+        *channel =
+            ((from * (FULL - intensity as u32) + to * intensity as u32 + FULL / 2) / FULL) as u8;
+    }
+    color
+}
+
+#[cfg(test)]
+mod phosphor_tests {
+    use super::*;
+
+    /// Lights the pixel at (0, 0) for one frame, then leaves it dark.
+    const BLINK_ONCE_ROM: [u8; 19] = [
+        0xA2, 0x12, // i := pixel
+        0xD0, 0x01, // sprite v0 v0 1
+        0x61, 0x01, // v1 := 1
+        0xF1, 0x15, // delay := v1
+        0xF2, 0x07, // v2 := delay
+        0x32, 0x00, // if v2 != 0 then
+        0x12, 0x08, //   jump 0x208
+        0xD0, 0x01, // sprite v0 v0 1
+        0x12, 0x10, // jump 0x210
+        0x80, // pixel
+    ];
+
+    /// Draws and erases the pixel at (0, 0) on alternate frames, the way a
+    /// sprite moved in place flickers.
+    const FLICKER_ROM: [u8; 17] = [
+        0xA2, 0x10, // i := pixel
+        0xD0, 0x01, // sprite v0 v0 1
+        0x61, 0x01, // v1 := 1
+        0xF1, 0x15, // delay := v1
+        0xF2, 0x07, // v2 := delay
+        0x32, 0x00, // if v2 != 0 then
+        0x12, 0x08, //   jump 0x208
+        0x12, 0x02, // jump 0x202
+        0x80, // pixel
+    ];
+
+    /// The intensity of pixel (0, 0) after each of `frames` frames.
+    fn levels(rom: &[u8], mut phosphor: Phosphor, frames: usize) -> Vec<u8> {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(rom).unwrap();
+        (0..frames)
+            .map(|_| {
+                cpu.run_frame().unwrap();
+                phosphor.update(&cpu);
+                phosphor.intensity()[0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_mixes_colors() {
+        let (off, on) = ([0, 0x20, 0xFF, 0xFF], [0xFF, 0x20, 0, 0xFF]);
+        assert_eq!(mix(off, on, 0), off);
+        assert_eq!(mix(off, on, 0xFF), on);
+        assert_eq!(mix(off, on, 0x80), [0x80, 0x20, 0x7F, 0xFF]);
+    }
+
+    #[test]
+    fn it_decays_over_the_given_frames() {
+        let phosphor = Phosphor::decay(4, Palette::monochrome(), 1);
+        assert_eq!(
+            levels(&BLINK_ONCE_ROM, phosphor, 6),
+            [0xFF, 191, 127, 63, 0, 0]
+        );
+
+        let phosphor = Phosphor::decay(1, Palette::monochrome(), 1);
+        assert_eq!(levels(&BLINK_ONCE_ROM, phosphor, 3), [0xFF, 0, 0]);
+    }
+
+    #[test]
+    fn it_counts_frames_run_between_updates() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&BLINK_ONCE_ROM).unwrap();
+        let mut phosphor = Phosphor::decay(4, Palette::monochrome(), 1);
+        cpu.run_frame().unwrap();
+        phosphor.update(&cpu);
+        cpu.run_frame().unwrap();
+        cpu.run_frame().unwrap();
+        phosphor.update(&cpu);
+        assert_eq!(phosphor.intensity()[0], 127);
+    }
+
+    #[test]
+    fn it_blends_away_xor_flicker() {
+        let unfiltered = levels(
+            &FLICKER_ROM,
+            Phosphor::decay(1, Palette::monochrome(), 1),
+            6,
+        );
+        assert_eq!(unfiltered, [0xFF, 0, 0xFF, 0, 0xFF, 0]);
+
+        let blended = levels(&FLICKER_ROM, Phosphor::blend(Palette::monochrome(), 1), 6);
+        assert_eq!(blended, [0x80; 6]);
+    }
+
+    #[test]
+    fn it_renders_faded_rgba() {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&BLINK_ONCE_ROM).unwrap();
+        let palette = Palette::amber();
+        let mut phosphor = Phosphor::decay(2, palette.clone(), 2);
+        for _ in 0..2 {
+            cpu.run_frame().unwrap();
+            phosphor.update(&cpu);
+        }
+
+        assert_eq!((phosphor.width(), phosphor.height()), (128, 64));
+        assert_eq!(phosphor.rgba().len(), 128 * 64 * 4);
+        let half = mix(palette.color(0), palette.color(1), 0x7F);
+        assert_eq!(phosphor.rgba()[..4], half);
+        assert_eq!(phosphor.rgba()[128 * 4 + 4..128 * 4 + 8], half);
+        assert_eq!(phosphor.rgba()[8..12], palette.color(0));
+    }
+}
@@ -0,0 +1,187 @@
+use wasm_bindgen::prelude::*;
+
+use crate::state::SaveStateError;
+
+/// Where Fx55/Fx65 leave I after storing or loading V0 through Vx.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LoadStoreIncrement {
+    /// I is left unchanged.
+    #[default]
+    None = 0,
+    /// I points at the last register accessed, as on CHIP-48.
+    ByX = 1,
+    /// I points one past the last register accessed, as on the COSMAC VIP.
+    ByXPlusOne = 2,
+}
+
+/// Switches for the instructions whose behaviour differs between CHIP-8
+/// interpreters.
+///
+/// `Quirks::default()` keeps the interpretation this crate has always used;
+/// the named presets match the platforms ROMs are usually written for.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8xy6/8xyE shift Vy into Vx instead of shifting Vx in place.
+    pub shift_uses_vy: bool,
+    /// How far Fx55/Fx65 advance I.
+    pub load_store_increment: LoadStoreIncrement,
+    /// Bnnn jumps to nnn plus Vx (x being the high nibble of nnn) instead of V0.
+    pub jump_uses_vx: bool,
+    /// 8xy1/8xy2/8xy3 reset VF to 0.
+    pub logic_resets_vf: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around.
+    pub clip_sprites: bool,
+    /// Dxyn waits for the next vertical blank before drawing.
+    pub display_wait: bool,
+}
+
+#[wasm_bindgen]
+impl Quirks {
+    /// The original RCA COSMAC VIP interpreter.
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increment: LoadStoreIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            display_wait: true,
+        }
+    }
+
+    /// CHIP-48 for the HP-48 calculators, which leaves I one short after
+    /// Fx55/Fx65.
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increment: LoadStoreIncrement::ByX,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1.
+    pub fn super_chip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increment: LoadStoreIncrement::None,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    /// XO-CHIP as implemented by Octo.
+    pub fn xo_chip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increment: LoadStoreIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+        }
+    }
+}
+
+/// Bits taken by the on/off switches; `load_store_increment` sits above them.
+const SWITCH_BITS: u8 = 5;
+
+impl Quirks {
+    /// Packs the quirks into a byte: the switches one bit each in
+    /// declaration order, then `load_store_increment` in the next two bits.
+    pub(crate) fn to_bits(self) -> u8 {
+        let switches: u8 = [
+            self.shift_uses_vy,
+            self.jump_uses_vx,
+            self.logic_resets_vf,
+            self.clip_sprites,
+            self.display_wait,
+        ]
+        .iter()
+        .enumerate()
+        .map(|(bit, &on)| (on as u8) << bit)
+        .sum();
+        switches | (self.load_store_increment as u8) << SWITCH_BITS
+    }
+
+    /// Unpacks a byte from `to_bits`, or from the layout of the given older
+    /// save state version.
+    pub(crate) fn from_bits(bits: u8, version: u16) -> Result<Quirks, SaveStateError> {
+        let bit = |n: u8| bits & (1 << n) != 0;
+        if version < 6 {
+            // One bit per switch, with I advancing by x + 1 in bit 1 and by
+            // x in bit 6.
+            let load_store_increment = match (bit(1), bit(6)) {
+                (true, _) => LoadStoreIncrement::ByXPlusOne,
+                (false, true) => LoadStoreIncrement::ByX,
+                (false, false) => LoadStoreIncrement::None,
+            };
+            return Ok(Quirks {
+                shift_uses_vy: bit(0),
+                load_store_increment,
+                jump_uses_vx: bit(2),
+                logic_resets_vf: bit(3),
+                clip_sprites: bit(4),
+                display_wait: bit(5),
+            });
+        }
+
+        let load_store_increment = match bits >> SWITCH_BITS {
+            0 => LoadStoreIncrement::None,
+            1 => LoadStoreIncrement::ByX,
+            2 => LoadStoreIncrement::ByXPlusOne,
+            _ => return Err(SaveStateError::Invalid("quirks")),
+        };
+        Ok(Quirks {
+            shift_uses_vy: bit(0),
+            load_store_increment,
+            jump_uses_vx: bit(1),
+            logic_resets_vf: bit(2),
+            clip_sprites: bit(3),
+            display_wait: bit(4),
+        })
+    }
+}
+
+#[cfg(test)]
+mod quirks_tests {
+    use super::*;
+    use crate::state::VERSION;
+
+    #[test]
+    fn it_round_trips_through_bits() {
+        for quirks in [
+            Quirks::default(),
+            Quirks::cosmac_vip(),
+            Quirks::chip48(),
+            Quirks::super_chip(),
+            Quirks::xo_chip(),
+        ]
+        .iter()
+        {
+            assert_eq!(Quirks::from_bits(quirks.to_bits(), VERSION), Ok(*quirks));
+        }
+        assert_eq!(
+            Quirks::from_bits(0b0110_0000, VERSION),
+            Err(SaveStateError::Invalid("quirks"))
+        );
+    }
+
+    #[test]
+    fn it_migrates_the_old_bit_layout() {
+        assert_eq!(Quirks::from_bits(0b0011_1011, 5), Ok(Quirks::cosmac_vip()));
+        assert_eq!(Quirks::from_bits(0b0101_0100, 5), Ok(Quirks::chip48()));
+        assert_eq!(Quirks::from_bits(0b0001_0100, 4), Ok(Quirks::super_chip()));
+    }
+
+    #[test]
+    fn it_tells_chip48_from_super_chip() {
+        assert_ne!(Quirks::chip48(), Quirks::super_chip());
+    }
+}
@@ -0,0 +1,224 @@
+//! Random number sources for Cxkk.
+
+use std::error::Error;
+use std::fmt;
+use wasm_bindgen::prelude::*;
+
+use crate::state::SaveStateError;
+
+/// Where Cxkk gets its random bytes from.
+///
+/// A source's state is stored in save states, so a restored machine draws the
+/// same numbers the original would have.
+pub trait RandomSource: fmt::Debug {
+    /// The next random byte, before Cxkk masks it with kk.
+    fn next_byte(&mut self) -> u8;
+
+    /// Names the source in save states, which only restore into a source
+    /// of the same name. At most 255 bytes.
+    fn name(&self) -> &'static str;
+
+    /// The generator state to put in save states, at most 255 bytes.
+    /// Sources that cannot be restored may leave it empty.
+    fn state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restores a state returned by `state`, leaving the source as it was
+    /// if `state` is not one. Sources that do not override this cannot be
+    /// restored, so save states using them fail to load.
+    fn set_state(&mut self, _state: &[u8]) -> Result<(), SaveStateError> {
+        Err(SaveStateError::Invalid("random source state"))
+    }
+}
+
+/// Marsaglia's 32-bit xorshift generator. The default source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct XorShift {
+    state: u32,
+}
+
+impl XorShift {
+    /// Seed used by `Cpu::new`.
+    pub const DEFAULT_SEED: u32 = 0x2545_F491;
+
+    /// Xorshift gets stuck on zero, so a zero seed is replaced with
+    /// `DEFAULT_SEED`.
+    pub fn new(seed: u32) -> XorShift {
+        XorShift {
+            state: if seed == 0 {
+                XorShift::DEFAULT_SEED
+            } else {
+                seed
+            },
+        }
+    }
+}
+
+impl Default for XorShift {
+    fn default() -> Self {
+        XorShift::new(XorShift::DEFAULT_SEED)
+    }
+}
+
+impl RandomSource for XorShift {
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 24) as u8
+    }
+
+    fn name(&self) -> &'static str {
+        "xorshift"
+    }
+
+    fn state(&self) -> Vec<u8> {
+        self.state.to_le_bytes().to_vec()
+    }
+
+    fn set_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        match *state {
+            [a, b, c, d] => {
+                *self = XorShift::new(u32::from_le_bytes([a, b, c, d]));
+                Ok(())
+            }
+            _ => Err(SaveStateError::Invalid("random source state")),
+        }
+    }
+}
+
+/// Bytes of the VIP interpreter the Cxkk routine reads from.
+pub const VIP_PAGE_SIZE: usize = 0x100;
+
+/// An interpreter page that is not `VIP_PAGE_SIZE` bytes long; holds the
+/// length given.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidVipPage(pub usize);
+
+impl fmt::Display for InvalidVipPage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "expected {} bytes of the VIP interpreter, got {}",
+            VIP_PAGE_SIZE, self.0
+        )
+    }
+}
+
+impl Error for InvalidVipPage {}
+
+impl From<InvalidVipPage> for JsValue {
+    fn from(err: InvalidVipPage) -> JsValue {
+        JsValue::from_str(&err.to_string())
+    }
+}
+
+/// The COSMAC VIP interpreter's Cxkk routine.
+///
+/// The VIP keeps a 16-bit counter in register R9. Each call advances it, reads
+/// the byte of the interpreter's own code page (0x0100-0x01FF) indexed by its
+/// low byte, adds that to the high byte and keeps the sum as both the new
+/// high byte and the result. The interpreter ROM is not distributed with this
+/// crate, so its page has to be supplied from a dump of the VIP's memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CosmacVip {
+    r9: u16,
+    page: [u8; VIP_PAGE_SIZE],
+}
+
+impl CosmacVip {
+    /// Starts the routine with R9 at `seed`, reading `page`, the interpreter
+    /// bytes at 0x0100-0x01FF.
+    pub fn new(seed: u16, page: [u8; VIP_PAGE_SIZE]) -> CosmacVip {
+        CosmacVip { r9: seed, page }
+    }
+}
+
+impl RandomSource for CosmacVip {
+    fn next_byte(&mut self) -> u8 {
+        self.r9 = self.r9.wrapping_add(1);
+        let [high, low] = self.r9.to_be_bytes();
+        let byte = self.page[low as usize].wrapping_add(high);
+        self.r9 = u16::from_be_bytes([byte, low]);
+        byte
+    }
+
+    fn name(&self) -> &'static str {
+        "cosmac-vip"
+    }
+
+    fn state(&self) -> Vec<u8> {
+        self.r9.to_le_bytes().to_vec()
+    }
+
+    fn set_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        match *state {
+            [low, high] => {
+                self.r9 = u16::from_le_bytes([low, high]);
+                Ok(())
+            }
+            _ => Err(SaveStateError::Invalid("random source state")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod random_tests {
+    use super::*;
+
+    #[test]
+    fn it_repeats_xorshift_sequences_for_a_seed() {
+        let mut a = XorShift::new(42);
+        let mut b = XorShift::new(42);
+        let bytes: Vec<u8> = (0..32).map(|_| a.next_byte()).collect();
+        assert!(bytes.iter().all(|&byte| byte == b.next_byte()));
+        assert!(bytes.iter().any(|&byte| byte != bytes[0]));
+        assert_eq!(XorShift::new(0), XorShift::default());
+    }
+
+    #[test]
+    fn it_restores_generator_state() {
+        let mut rng = XorShift::new(7);
+        rng.next_byte();
+        let mut restored = XorShift::new(1);
+        restored.set_state(&rng.state()).unwrap();
+        assert_eq!(restored.next_byte(), rng.next_byte());
+    }
+
+    #[test]
+    fn it_rejects_states_of_another_size() {
+        let mut rng = XorShift::new(7);
+        let err = Err(SaveStateError::Invalid("random source state"));
+        assert_eq!(rng.set_state(&[1, 2]), err);
+        assert_eq!(rng, XorShift::new(7));
+        assert_eq!(CosmacVip::new(0, [0; VIP_PAGE_SIZE]).set_state(&[1]), err);
+    }
+
+    #[test]
+    fn it_refuses_to_restore_sources_without_set_state() {
+        #[derive(Debug)]
+        struct Constant;
+        impl RandomSource for Constant {
+            fn next_byte(&mut self) -> u8 {
+                4
+            }
+
+            fn name(&self) -> &'static str {
+                "constant"
+            }
+        }
+        assert!(Constant.set_state(&[]).is_err());
+    }
+
+    #[test]
+    fn it_follows_the_vip_routine() {
+        let mut page = [0; 256];
+        page[0x01] = 0x10;
+        page[0x02] = 0x05;
+        let mut rng = CosmacVip::new(0x2000, page);
+        assert_eq!(rng.next_byte(), 0x30);
+        assert_eq!(rng.next_byte(), 0x35);
+        assert_eq!(rng.state(), vec![0x02, 0x35]);
+    }
+}
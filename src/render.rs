@@ -0,0 +1,295 @@
+//! RGBA8 rendering of the display.
+
+use wasm_bindgen::prelude::*;
+
+use crate::chip8::Cpu;
+use crate::display::Display;
+
+/// Colors for each value of `Display::get_color`.
+///
+/// A palette has 2 entries (off, on) or 4, one per combination of the two
+/// XO-CHIP planes, indexed 0-3. With only two entries, a pixel lit in any
+/// plane uses the "on" color.
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    colors: Vec<[u8; 4]>,
+}
+
+#[wasm_bindgen]
+impl Palette {
+    /// Builds a palette from 0xRRGGBB colors, returning nothing unless there
+    /// are 2 or 4 of them.
+    pub fn from_rgb(colors: &[u32]) -> Option<Palette> {
+        match colors.len() {
+            2 | 4 => Some(Palette {
+                colors: colors.iter().map(|&rgb| rgba(rgb)).collect(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Black and white.
+    pub fn monochrome() -> Palette {
+        Palette::fixed(&[0x000000, 0xFFFFFF])
+    }
+
+    /// Green phosphor terminal.
+    pub fn classic_green() -> Palette {
+        Palette::fixed(&[0x0A1A0A, 0x41FF00])
+    }
+
+    /// Amber phosphor terminal.
+    pub fn amber() -> Palette {
+        Palette::fixed(&[0x1A0F00, 0xFFB000])
+    }
+
+    /// Greenish handheld LCD.
+    pub fn lcd() -> Palette {
+        Palette::fixed(&[0xC7D3A5, 0x2E3B1E, 0x6B7A4A, 0x0F170A])
+    }
+
+    /// Octo's default background, fill, second fill and blend colors.
+    pub fn octo() -> Palette {
+        Palette::fixed(&[0x996600, 0xFFCC00, 0xFF6600, 0x662200])
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+}
+
+impl Palette {
+    fn fixed(colors: &[u32]) -> Palette {
+        Palette::from_rgb(colors).expect("built-in palettes have a valid size")
+    }
+
+    /// RGBA for a `Display::get_color` value.
+    pub fn color(&self, index: u8) -> [u8; 4] {
+        self.colors[self.index(index) as usize]
+    }
+
+    /// The palette entry used for a `Display::get_color` value.
+    pub fn index(&self, color: u8) -> u8 {
+        if (color as usize) < self.colors.len() && (self.colors.len() > 2 || color == 0) {
+            color
+        } else {
+            1
+        }
+    }
+
+    /// The colors as packed RGB triples, as image formats store palettes.
+    pub fn rgb(&self) -> Vec<u8> {
+        self.colors
+            .iter()
+            .flat_map(|color| color[..3].to_vec())
+            .collect()
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::monochrome()
+    }
+}
+
+fn rgba(rgb: u32) -> [u8; 4] {
+    let [_, r, g, b] = rgb.to_be_bytes();
+    [r, g, b, 0xFF]
+}
+
+/// Draws `display` into `out` as RGBA8 rows, each pixel `scale` x `scale`,
+/// resizing `out` to fit.
+pub fn render_rgba(display: &Display, palette: &Palette, scale: u32, out: &mut Vec<u8>) {
+    let (width, height) = (display.width(), display.height());
+    fill_scaled(width, height, scale, out, |i| {
+        palette.color(display.get_color(i))
+    });
+}
+
+/// Fills `out` with a `width` x `height` RGBA8 image scaled up by `scale`,
+/// taking the color of pixel `i` (counted along the rows) from `color`.
+pub(crate) fn fill_scaled(
+    width: u32,
+    height: u32,
+    scale: u32,
+    out: &mut Vec<u8>,
+    color: impl Fn(usize) -> [u8; 4],
+) {
+    let scale = scale.max(1) as usize;
+    let width = width as usize;
+    let height = height as usize;
+    let row_bytes = width * scale * 4;
+    out.resize(row_bytes * height * scale, 0);
+
+    for (y, rows) in out.chunks_exact_mut(row_bytes * scale).enumerate() {
+        let (first, rest) = rows.split_at_mut(row_bytes);
+        for (x, pixel) in first.chunks_exact_mut(scale * 4).enumerate() {
+            let color = color(y * width + x);
+            for channel in pixel.chunks_exact_mut(4) {
+                channel.copy_from_slice(&color);
+            }
+        }
+        for row in rest.chunks_exact_mut(row_bytes) {
+            row.copy_from_slice(first);
+        }
+    }
+}
+
+/// Draws `display` into `out` as palette indices, one byte per pixel,
+/// stretched to `width` x `height` with nearest-neighbor sampling.
+pub fn render_indices(
+    display: &Display,
+    palette: &Palette,
+    width: u32,
+    height: u32,
+    out: &mut Vec<u8>,
+) {
+    let (columns, rows) = (display.width(), display.height());
+    out.clear();
+    out.reserve((width * height) as usize);
+    for y in 0..height {
+        let row = y * rows / height;
+        for x in 0..width {
+            let i = (row * columns + x * columns / width) as usize;
+            out.push(palette.index(display.get_color(i)));
+        }
+    }
+}
+
+/// Keeps an RGBA8 image of a machine's display for `putImageData` or an
+/// image encoder.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct Renderer {
+    palette: Palette,
+    scale: u32,
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl Renderer {
+    #[wasm_bindgen(constructor)]
+    pub fn new(palette: Palette, scale: u32) -> Renderer {
+        Renderer {
+            palette,
+            scale: scale.max(1),
+            width: 0,
+            height: 0,
+            rgba: Vec::new(),
+        }
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn set_scale(&mut self, scale: u32) {
+        self.scale = scale.max(1);
+    }
+
+    /// Redraws the image from `cpu`'s display.
+    pub fn render(&mut self, cpu: &Cpu) {
+        let display = cpu.display();
+        self.width = display.width() * self.scale;
+        self.height = display.height() * self.scale;
+        render_rgba(display, &self.palette, self.scale, &mut self.rgba);
+    }
+
+    /// Width of the last rendered image in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Pointer to `width * height * 4` bytes of RGBA in wasm memory.
+    pub fn rgba_ptr(&self) -> *const u8 {
+        self.rgba.as_ptr()
+    }
+}
+
+impl Renderer {
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+}
+
+#[cfg(test)]
+mod render_tests {
+    use super::*;
+
+    #[test]
+    fn it_builds_palettes_of_valid_sizes() {
+        assert!(Palette::from_rgb(&[0, 1]).is_some());
+        assert!(Palette::from_rgb(&[0; 4]).is_some());
+        assert!(Palette::from_rgb(&[0; 3]).is_none());
+        assert!(Palette::from_rgb(&[0; 16]).is_none());
+        assert_eq!(Palette::amber().color(1), [0xFF, 0xB0, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn it_maps_plane_colors() {
+        let two = Palette::classic_green();
+        assert_eq!(two.color(2), two.color(1));
+        assert_eq!(two.color(3), two.color(1));
+
+        let four = Palette::octo();
+        assert_eq!(four.color(2), [0xFF, 0x66, 0x00, 0xFF]);
+        assert_eq!(four.color(3), [0x66, 0x22, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn it_renders_scaled_rgba() {
+        let mut display = Display::new_empty();
+        display.draw_bytes(1, 0, &[0b1000_0000], false);
+        let mut rgba = Vec::new();
+        render_rgba(&display, &Palette::monochrome(), 3, &mut rgba);
+
+        let width = 64 * 3;
+        assert_eq!(rgba.len(), width * 32 * 3 * 4);
+        let pixel = |x: usize, y: usize| &rgba[(y * width + x) * 4..(y * width + x) * 4 + 4];
+        assert_eq!(pixel(2, 0), [0, 0, 0, 0xFF]);
+        for (x, y) in [(3, 0), (5, 0), (3, 2), (5, 2)] {
+            assert_eq!(pixel(x, y), [0xFF, 0xFF, 0xFF, 0xFF]);
+        }
+        assert_eq!(pixel(6, 0), [0, 0, 0, 0xFF]);
+        assert_eq!(pixel(3, 3), [0, 0, 0, 0xFF]);
+    }
+
+    #[test]
+    fn it_renders_stretched_palette_indices() {
+        let mut display = Display::new_empty();
+        display.select_planes(0b11);
+        display.draw_bytes(0, 0, &[0x80, 0x40], false);
+        let mut indices = Vec::new();
+        render_indices(&display, &Palette::octo(), 128, 32, &mut indices);
+        assert_eq!(indices.len(), 128 * 32);
+        assert_eq!(indices[..6], [1, 1, 2, 2, 0, 0]);
+
+        render_indices(&display, &Palette::monochrome(), 32, 16, &mut indices);
+        assert_eq!(indices[..2], [1, 0]);
+    }
+
+    #[test]
+    fn it_follows_resolution_changes() {
+        let mut cpu = Cpu::new();
+        let mut renderer = Renderer::new(Palette::lcd(), 2);
+        renderer.render(&cpu);
+        assert_eq!((renderer.width(), renderer.height()), (128, 64));
+
+        cpu.load_rom(&[0x00, 0xFF]).unwrap();
+        cpu.step().unwrap();
+        renderer.render(&cpu);
+        assert_eq!((renderer.width(), renderer.height()), (256, 128));
+        assert_eq!(renderer.rgba().len(), 256 * 128 * 4);
+    }
+}
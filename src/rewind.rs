@@ -0,0 +1,347 @@
+//! Rewind history built from save states.
+//!
+//! Only the newest snapshot is kept whole. Every older snapshot is stored as
+//! a backward delta against the one after it, so stepping back applies one
+//! delta to the newest state, and the oldest snapshot can be dropped at any
+//! time without touching the others.
+
+use std::collections::VecDeque;
+use wasm_bindgen::prelude::*;
+
+use crate::chip8::Cpu;
+use crate::state::SaveStateError;
+
+/// Enough for a minute of typical games snapshotted every frame; a ROM that
+/// redraws a sprite every frame uses about 210 KiB of it.
+pub const DEFAULT_BUDGET: usize = 4 * 1024 * 1024;
+
+/// Unchanged runs shorter than this are folded into the surrounding change,
+/// since a new run header costs about as much.
+const MIN_GAP: usize = 4;
+
+struct Delta {
+    /// Frame the reconstructed snapshot was taken on.
+    frame: u64,
+    bytes: Vec<u8>,
+}
+
+#[wasm_bindgen]
+pub struct Rewind {
+    interval: u32,
+    budget: usize,
+    frame: u64,
+    head: Option<(u64, Vec<u8>)>,
+    deltas: VecDeque<Delta>,
+    delta_bytes: usize,
+}
+
+#[wasm_bindgen]
+impl Rewind {
+    /// Snapshots every `interval` frames, keeping at most `budget` bytes of
+    /// history.
+    #[wasm_bindgen(constructor)]
+    pub fn new(interval: u32, budget: usize) -> Rewind {
+        Rewind {
+            interval: interval.max(1),
+            budget,
+            frame: 0,
+            head: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+        }
+    }
+
+    /// Call once per frame; takes a snapshot when one is due. Fails if the
+    /// machine cannot be saved.
+    pub fn record(&mut self, cpu: &Cpu) -> Result<(), SaveStateError> {
+        if self.head.is_some() {
+            self.frame += 1;
+            if !self.frame.is_multiple_of(self.interval as u64) {
+                return Ok(());
+            }
+        }
+
+        let state = cpu.save_state()?;
+        if let Some((frame, older)) = self.head.take() {
+            let bytes = diff(&state, &older);
+            self.delta_bytes += bytes.len();
+            self.deltas.push_back(Delta { frame, bytes });
+        }
+        self.head = Some((self.frame, state));
+        self.evict();
+        Ok(())
+    }
+
+    /// Restores the newest snapshot at least `frames` frames old, or the
+    /// oldest one kept. Returns how many frames were actually rewound.
+    pub fn rewind(&mut self, cpu: &mut Cpu, frames: u32) -> u32 {
+        let (mut head_frame, mut state) = match self.head.take() {
+            Some(head) => head,
+            None => return 0,
+        };
+        let target = self.frame.saturating_sub(frames as u64);
+
+        while head_frame > target {
+            let delta = match self.deltas.pop_back() {
+                Some(delta) => delta,
+                None => break,
+            };
+            self.delta_bytes -= delta.bytes.len();
+            state = patch(&state, &delta.bytes);
+            head_frame = delta.frame;
+        }
+
+        cpu.load_state(&state)
+            .expect("rewind snapshots are states this build wrote");
+        let rewound = self.frame - head_frame;
+        self.frame = head_frame;
+        self.head = Some((head_frame, state));
+        rewound as u32
+    }
+
+    /// How many frames back the oldest snapshot is.
+    pub fn frames_available(&self) -> u32 {
+        let oldest = match self.deltas.front() {
+            Some(delta) => delta.frame,
+            None => match &self.head {
+                Some((frame, _)) => *frame,
+                None => return 0,
+            },
+        };
+        (self.frame - oldest) as u32
+    }
+
+    /// Bytes of history currently held.
+    pub fn memory_used(&self) -> usize {
+        self.head.as_ref().map_or(0, |(_, state)| state.len()) + self.delta_bytes
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// Changes the memory budget, dropping the oldest snapshots if needed.
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict();
+    }
+
+    /// Forgets all history, e.g. after loading a new ROM.
+    pub fn clear(&mut self) {
+        self.frame = 0;
+        self.head = None;
+        self.deltas.clear();
+        self.delta_bytes = 0;
+    }
+
+    fn evict(&mut self) {
+        while self.memory_used() > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.delta_bytes -= delta.bytes.len(),
+                None => break,
+            }
+        }
+    }
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Rewind::new(1, DEFAULT_BUDGET)
+    }
+}
+
+/// Encodes how to turn `newer` back into `older`: the length of `older`,
+/// then runs of (unchanged bytes to skip, length, replacement bytes).
+fn diff(newer: &[u8], older: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    write_varint(&mut delta, older.len());
+
+    let differs = |offset: usize| newer.get(offset) != Some(&older[offset]);
+    let mut copied = 0;
+    let mut offset = 0;
+    while offset < older.len() {
+        if !differs(offset) {
+            offset += 1;
+            continue;
+        }
+        let start = offset;
+        let mut end = offset + 1;
+        while end < older.len() {
+            if (end..(end + MIN_GAP).min(older.len())).any(differs) {
+                end += 1;
+            } else {
+                break;
+            }
+        }
+        write_varint(&mut delta, start - copied);
+        write_varint(&mut delta, end - start);
+        delta.extend_from_slice(&older[start..end]);
+        copied = end;
+        offset = end;
+    }
+    delta
+}
+
+/// Applies a delta made by `diff` to `newer`.
+fn patch(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut cursor = delta;
+    let len = read_varint(&mut cursor);
+    let mut older = newer.to_vec();
+    older.resize(len, 0);
+
+    let mut offset = 0;
+    while !cursor.is_empty() {
+        offset += read_varint(&mut cursor);
+        let run = read_varint(&mut cursor);
+        older[offset..offset + run].copy_from_slice(&cursor[..run]);
+        cursor = &cursor[run..];
+        offset += run;
+    }
+    older
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[0];
+        *bytes = &bytes[1..];
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod rewind_tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::quirks::Quirks;
+
+    fn counting_cpu() -> Cpu {
+        let rom = assemble(": main loop v0 += 1 again").unwrap();
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&rom).unwrap();
+        cpu
+    }
+
+    fn run_frame(cpu: &mut Cpu) {
+        for _ in 0..2 {
+            cpu.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn it_round_trips_deltas() {
+        let older = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let mut newer = older.clone();
+        newer[1] = 0xFF;
+        newer[9] = 0xFF;
+        assert_eq!(patch(&newer, &diff(&newer, &older)), older);
+
+        let shorter = &older[..5];
+        assert_eq!(patch(&older, &diff(&older, shorter)), shorter);
+        assert_eq!(patch(shorter, &diff(shorter, &older)), older);
+        assert_eq!(diff(&older, &older), vec![12]);
+    }
+
+    #[test]
+    fn it_rewinds_to_earlier_frames() {
+        let mut cpu = counting_cpu();
+        let mut rewind = Rewind::default();
+        let mut states = Vec::new();
+        for _ in 0..10 {
+            rewind.record(&cpu).unwrap();
+            states.push(cpu.save_state().unwrap());
+            run_frame(&mut cpu);
+        }
+        assert_eq!(rewind.frames_available(), 9);
+
+        assert_eq!(rewind.rewind(&mut cpu, 3), 3);
+        assert_eq!(cpu.save_state().unwrap(), states[6]);
+        assert_eq!(rewind.rewind(&mut cpu, 0), 0);
+        assert_eq!(cpu.save_state().unwrap(), states[6]);
+        assert_eq!(rewind.rewind(&mut cpu, 100), 6);
+        assert_eq!(cpu.save_state().unwrap(), states[0]);
+    }
+
+    #[test]
+    fn it_snapshots_every_interval_frames() {
+        let mut cpu = counting_cpu();
+        let mut rewind = Rewind::new(4, DEFAULT_BUDGET);
+        let mut states = Vec::new();
+        for _ in 0..10 {
+            rewind.record(&cpu).unwrap();
+            states.push(cpu.save_state().unwrap());
+            run_frame(&mut cpu);
+        }
+        assert_eq!(rewind.rewind(&mut cpu, 1), 1);
+        assert_eq!(cpu.save_state().unwrap(), states[8]);
+        assert_eq!(rewind.rewind(&mut cpu, 1), 4);
+        assert_eq!(cpu.save_state().unwrap(), states[4]);
+    }
+
+    #[test]
+    fn it_stores_small_deltas_and_respects_the_budget() {
+        let mut cpu = counting_cpu();
+        let state_len = cpu.save_state().unwrap().len();
+        let mut rewind = Rewind::default();
+        for _ in 0..100 {
+            rewind.record(&cpu).unwrap();
+            run_frame(&mut cpu);
+        }
+        assert!(rewind.memory_used() < state_len + 100 * 16);
+
+        rewind.set_budget(state_len + 100);
+        assert!(rewind.memory_used() <= state_len + 100);
+        assert!(rewind.frames_available() > 0);
+        assert!(rewind.frames_available() < 99);
+    }
+
+    #[test]
+    fn it_keeps_a_minute_of_a_drawing_game_in_the_default_budget() {
+        // Bounces a digit that counts up around the screen, redrawing it
+        // every frame like a typical game loop.
+        let rom = assemble(
+            ": main
+               v3 := 1
+               v4 := 1
+               loop
+                 i := hex v2
+                 sprite v0 v1 5
+                 v0 += v3
+                 v1 += v4
+                 if v0 == 59 then v3 := -1
+                 if v0 == 0 then v3 := 1
+                 if v1 == 26 then v4 := -1
+                 if v1 == 0 then v4 := 1
+                 v2 += 1
+                 if v2 == 16 then v2 := 0
+                 i := hex v2
+                 sprite v0 v1 5
+               again",
+        )
+        .unwrap();
+        let mut cpu = Cpu::new();
+        cpu.set_quirks(Quirks::cosmac_vip());
+        cpu.load_rom(&rom).unwrap();
+
+        let mut rewind = Rewind::default();
+        for _ in 0..3600 {
+            rewind.record(&cpu).unwrap();
+            cpu.run_frame().unwrap();
+        }
+        assert!(rewind.memory_used() <= DEFAULT_BUDGET);
+        assert_eq!(rewind.frames_available(), 3599);
+    }
+}
@@ -0,0 +1,285 @@
+//! Binary save-state format.
+//!
+//! A state starts with a 6-byte header: the magic bytes `C8ST` followed by
+//! the format version as a little-endian `u16`. Every later multi-byte
+//! integer is little-endian too. Version 6 then holds, in order:
+//!
+//! | Field                          | Size                  |
+//! |--------------------------------|-----------------------|
+//! | I                              | 2                     |
+//! | PC                             | 2                     |
+//! | stack pointer                  | 1                     |
+//! | stack                          | 16 x 2                |
+//! | delay timer                    | 1                     |
+//! | sound timer                    | 1                     |
+//! | V0-VF                          | 16                    |
+//! | RPL flags                      | 16                    |
+//! | random source name length      | 1                     |
+//! | random source name             | length                |
+//! | random source state length     | 1                     |
+//! | random source state            | length                |
+//! | quirks bitfield                | 1                     |
+//! | vertical blank pending         | 1                     |
+//! | memory                         | 65536                 |
+//! | display width, height          | 2 + 2                 |
+//! | display plane mask             | 1                     |
+//! | display planes, one byte/pixel | 2 x width x height    |
+//! | keyboard, one byte/key         | 16                    |
+//! | key Fx0A awaits release, or FF | 1                     |
+//! | audio pattern loaded           | 1                     |
+//! | audio pattern                  | 16                    |
+//! | audio pitch                    | 1                     |
+//! | frames run                     | 8                     |
+//!
+//! Version 1 had a single byte, the last random number passed in from JS,
+//! where version 2 has the random source state. Versions 2 to 4 do not name
+//! the random source. Versions 1 and 2 end after the keyboard, version 3
+//! after the Fx0A key and version 4 after the audio pitch.
+//!
+//! The quirks byte has a bit for each on/off switch, in the order `Quirks`
+//! declares them, and the Fx55/Fx65 increment above those. Before version 6
+//! the increment took bits 1 and 6, with the switches around it.
+//!
+//! A state must be loaded by a build that knows its version; older versions
+//! are migrated on load, newer ones are rejected.
+
+use std::error::Error;
+use std::fmt;
+use wasm_bindgen::prelude::*;
+
+pub const MAGIC: [u8; 4] = *b"C8ST";
+pub const VERSION: u16 = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveStateError {
+    /// The data does not start with `MAGIC`.
+    BadMagic,
+    /// The state was written by a newer build, or is not a known version.
+    UnsupportedVersion(u16),
+    /// The data ended before every field was read.
+    Truncated,
+    /// A field holds a value no machine could be in.
+    Invalid(&'static str),
+    /// The state was written with a different random source than the one
+    /// the machine is using.
+    RandomSourceMismatch,
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::BadMagic => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported save state version {} (this build reads up to {})",
+                version, VERSION
+            ),
+            SaveStateError::Truncated => write!(f, "save state is truncated"),
+            SaveStateError::Invalid(field) => write!(f, "save state has an invalid {}", field),
+            SaveStateError::RandomSourceMismatch => {
+                write!(f, "save state was written with another random source")
+            }
+        }
+    }
+}
+
+impl Error for SaveStateError {}
+
+impl From<SaveStateError> for JsValue {
+    fn from(err: SaveStateError) -> JsValue {
+        JsValue::from_str(&err.to_string())
+    }
+}
+
+pub(crate) struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    /// Starts a state with the header for the current version.
+    pub(crate) fn new() -> StateWriter {
+        let mut writer = StateWriter { bytes: Vec::new() };
+        writer.bytes(&MAGIC);
+        writer.u16(VERSION);
+        writer
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes `bytes` after a one-byte length, failing if it does not fit.
+    pub(crate) fn short_bytes(
+        &mut self,
+        bytes: &[u8],
+        field: &'static str,
+    ) -> Result<(), SaveStateError> {
+        if bytes.len() > u8::MAX as usize {
+            return Err(SaveStateError::Invalid(field));
+        }
+        self.u8(bytes.len() as u8);
+        self.bytes(bytes);
+        Ok(())
+    }
+
+    pub(crate) fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    bytes: &'a [u8],
+    version: u16,
+}
+
+impl<'a> StateReader<'a> {
+    /// Checks the header, returning a reader positioned at the first field.
+    pub(crate) fn new(bytes: &'a [u8]) -> Result<StateReader<'a>, SaveStateError> {
+        let mut reader = StateReader { bytes, version: 0 };
+        if reader
+            .bytes(MAGIC.len())
+            .map_err(|_| SaveStateError::BadMagic)?
+            != MAGIC
+        {
+            return Err(SaveStateError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version == 0 || version > VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+        reader.version = version;
+        Ok(reader)
+    }
+
+    /// The format version the state was written with.
+    pub(crate) fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, SaveStateError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, SaveStateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Reads bytes written by `StateWriter::short_bytes`.
+    pub(crate) fn short_bytes(&mut self) -> Result<&'a [u8], SaveStateError> {
+        let len = self.u8()? as usize;
+        self.bytes(len)
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::Invalid("flag")),
+        }
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.bytes.len() < len {
+            return Err(SaveStateError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    /// Fails if anything is left over after the last field.
+    pub(crate) fn finish(self) -> Result<(), SaveStateError> {
+        if !self.bytes.is_empty() {
+            return Err(SaveStateError::Invalid("length"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod state_tests {
+    use super::*;
+
+    #[test]
+    fn it_writes_the_header() {
+        let bytes = StateWriter::new().finish();
+        assert_eq!(bytes, vec![b'C', b'8', b'S', b'T', VERSION as u8, 0]);
+    }
+
+    #[test]
+    fn it_round_trips_fields() {
+        let mut writer = StateWriter::new();
+        writer.u8(0xAB);
+        writer.u16(0x1234);
+        writer.u64(0x0102_0304_0506_0708);
+        writer.bool(true);
+        writer.bytes(&[1, 2, 3]);
+        writer.short_bytes(&[4, 5], "field").unwrap();
+        let bytes = writer.finish();
+
+        let mut reader = StateReader::new(&bytes).unwrap();
+        assert_eq!(reader.version(), VERSION);
+        assert_eq!(reader.u8(), Ok(0xAB));
+        assert_eq!(reader.u16(), Ok(0x1234));
+        assert_eq!(reader.u64(), Ok(0x0102_0304_0506_0708));
+        assert_eq!(reader.bool(), Ok(true));
+        assert_eq!(reader.bytes(3), Ok(&[1u8, 2, 3][..]));
+        assert_eq!(reader.short_bytes(), Ok(&[4u8, 5][..]));
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn it_rejects_bad_headers() {
+        assert_eq!(
+            StateReader::new(b"nope!!").err(),
+            Some(SaveStateError::BadMagic)
+        );
+        assert_eq!(
+            StateReader::new(b"C8").err(),
+            Some(SaveStateError::BadMagic)
+        );
+        assert_eq!(
+            StateReader::new(&[b'C', b'8', b'S', b'T', 0xFF, 0x00]).err(),
+            Some(SaveStateError::UnsupportedVersion(0xFF))
+        );
+    }
+
+    #[test]
+    fn it_rejects_short_bytes_over_255() {
+        let mut writer = StateWriter::new();
+        assert_eq!(
+            writer.short_bytes(&[0; 256], "field"),
+            Err(SaveStateError::Invalid("field"))
+        );
+        assert_eq!(writer.finish().len(), 6);
+    }
+
+    #[test]
+    fn it_reports_truncation() {
+        let mut reader = StateReader::new(&[b'C', b'8', b'S', b'T', 1, 0, 7]).unwrap();
+        assert_eq!(reader.u16(), Err(SaveStateError::Truncated));
+    }
+}
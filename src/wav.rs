@@ -0,0 +1,163 @@
+//! Records emulator audio to WAV files.
+
+use std::convert::TryFrom;
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crate::audio::Audio;
+use crate::chip8::{Cpu, FrameSummary, TIMER_RATE};
+
+const HEADER_SIZE: u32 = 44;
+const BITS_PER_SAMPLE: u16 = 16;
+
+/// Writes the audio of every emulated frame, silent ones included, as mono
+/// 16-bit PCM.
+///
+/// Each frame gets exactly as many samples as 1/60 s holds at the sample
+/// rate, with fractions carried into later frames, so the recording stays
+/// in step with the frames it was made from. Nothing is played; this works
+/// without a sound device.
+pub struct WavRecorder<W: Write + Seek> {
+    out: W,
+    audio: Audio,
+    sample_rate: u32,
+    frames: u64,
+    samples: u64,
+    buffer: Vec<f32>,
+}
+
+impl<W: Write + Seek> WavRecorder<W> {
+    /// Starts a recording at `sample_rate`, writing a header whose sizes are
+    /// filled in by `finish`.
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<WavRecorder<W>> {
+        write_header(&mut out, sample_rate, 0)?;
+        Ok(WavRecorder {
+            out,
+            audio: Audio::new(sample_rate as f32),
+            sample_rate,
+            frames: 0,
+            samples: 0,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// The generator used for the recording, to set its tone and volume.
+    pub fn audio_mut(&mut self) -> &mut Audio {
+        &mut self.audio
+    }
+
+    /// Appends the audio of the frame `cpu.run_frame()` just ran.
+    pub fn record_frame(&mut self, cpu: &Cpu, summary: &FrameSummary) -> io::Result<()> {
+        self.frames += 1;
+        let due = self.frames * self.sample_rate as u64 / TIMER_RATE as u64;
+        self.buffer.resize((due - self.samples) as usize, 0.0);
+        self.audio
+            .render(cpu, summary.sound_active, &mut self.buffer);
+
+        let mut bytes = Vec::with_capacity(self.buffer.len() * 2);
+        for &sample in &self.buffer {
+            let pcm = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+            bytes.extend_from_slice(&pcm.to_le_bytes());
+        }
+        self.out.write_all(&bytes)?;
+        self.samples = due;
+        Ok(())
+    }
+
+    /// Samples written so far.
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// Fills in the header sizes and hands back the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let data_size = u32::try_from(self.samples * 2)
+            .ok()
+            .filter(|&size| size <= u32::MAX - (HEADER_SIZE - 8))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "recording too long for WAV")
+            })?;
+        self.out.seek(SeekFrom::Start(0))?;
+        write_header(&mut self.out, self.sample_rate, data_size)?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn write_header(out: &mut impl Write, sample_rate: u32, data_size: u32) -> io::Result<()> {
+    let block_align = BITS_PER_SAMPLE / 8;
+    let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(HEADER_SIZE - 8 + data_size).to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    header.extend_from_slice(&1u16.to_le_bytes()); // mono
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_size.to_le_bytes());
+    out.write_all(&header)
+}
+
+#[cfg(test)]
+mod wav_tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ])
+    }
+
+    /// Runs a program that beeps for its first two frames.
+    fn record(sample_rate: u32, frames: usize) -> Vec<u8> {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&[0x60, 0x02, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+        let mut recorder = WavRecorder::new(Cursor::new(Vec::new()), sample_rate).unwrap();
+        for _ in 0..frames {
+            let summary = cpu.run_frame().unwrap();
+            recorder.record_frame(&cpu, &summary).unwrap();
+        }
+        recorder.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn it_writes_a_pcm_header() {
+        let wav = record(6000, 6);
+        assert_eq!(wav[..4], *b"RIFF");
+        assert_eq!(u32_at(&wav, 4), 36 + 1200);
+        assert_eq!(wav[8..16], *b"WAVEfmt ");
+        assert_eq!(u32_at(&wav, 24), 6000);
+        assert_eq!(wav[36..40], *b"data");
+        assert_eq!(u32_at(&wav, 40), 1200);
+        assert_eq!(wav.len(), 44 + 1200);
+    }
+
+    #[test]
+    fn it_records_sound_and_silence_in_step_with_frames() {
+        let wav = record(6000, 6);
+        let samples: Vec<i16> = wav[44..]
+            .chunks(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        assert!(samples[..200].iter().any(|&sample| sample != 0));
+        assert!(samples[190..200].iter().any(|&sample| sample != 0));
+        assert!(samples[200..].iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn it_carries_fractional_samples_between_frames() {
+        // 22050 Hz is 367.5 samples per frame.
+        assert_eq!(record(22_050, 1).len(), 44 + 367 * 2);
+        assert_eq!(record(22_050, 2).len(), 44 + 735 * 2);
+        assert_eq!(record(22_050, 60).len(), 44 + 22_050 * 2);
+    }
+}
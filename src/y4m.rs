@@ -0,0 +1,163 @@
+//! Streams emulator video as YUV4MPEG2 (Y4M).
+
+use std::io::{self, Write};
+
+use crate::chip8::{Cpu, TIMER_RATE};
+use crate::render::{render_indices, Palette};
+
+/// Writes every emulated frame as an uncompressed 4:4:4 Y4M video at 60
+/// frames per second, for frame-exact analysis or piping into an encoder.
+///
+/// The video takes the size of the first frame times the scale; Y4M cannot
+/// change size mid-stream, so later resolution changes are scaled to fit.
+/// Colors are converted with BT.601 in the limited (16-235) range most
+/// encoders assume for Y4M input.
+pub struct Y4mWriter<W: Write> {
+    out: W,
+    /// Y, Cb and Cr for each palette entry.
+    colors: Vec<[u8; 3]>,
+    palette: Palette,
+    scale: u32,
+    size: Option<(u32, u32)>,
+    frames: u64,
+    indices: Vec<u8>,
+    frame: Vec<u8>,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Starts a stream; the header goes out with the first frame.
+    pub fn new(out: W, palette: Palette, scale: u32) -> Y4mWriter<W> {
+        let colors = (0..palette.len() as u8)
+            .map(|index| ycbcr(palette.color(index)))
+            .collect();
+        Y4mWriter {
+            out,
+            colors,
+            palette,
+            scale: scale.max(1),
+            size: None,
+            frames: 0,
+            indices: Vec::new(),
+            frame: Vec::new(),
+        }
+    }
+
+    /// Appends `cpu`'s display as the next frame.
+    pub fn record_frame(&mut self, cpu: &Cpu) -> io::Result<()> {
+        let display = cpu.display();
+        let (width, height) = match self.size {
+            Some(size) => size,
+            None => {
+                let size = (display.width() * self.scale, display.height() * self.scale);
+                writeln!(
+                    self.out,
+                    "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                    size.0, size.1, TIMER_RATE
+                )?;
+                self.size = Some(size);
+                size
+            }
+        };
+
+        render_indices(display, &self.palette, width, height, &mut self.indices);
+        self.frame.clear();
+        self.frame.extend_from_slice(b"FRAME\n");
+        for component in 0..3 {
+            let colors = &self.colors;
+            self.frame.extend(
+                self.indices
+                    .iter()
+                    .map(|&index| colors[index as usize][component]),
+            );
+        }
+        self.out.write_all(&self.frame)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Frames written so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Flushes the stream and hands back the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Limited-range BT.601 Y, Cb and Cr for an RGBA color.
+fn ycbcr([r, g, b, _]: [u8; 4]) -> [u8; 3] {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    let y = 16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0;
+    let cb = 128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0;
+    let cr = 128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0;
+    [y.round() as u8, cb.round() as u8, cr.round() as u8]
+}
+
+#[cfg(test)]
+mod y4m_tests {
+    use super::*;
+
+    /// Draws the digit 0 at (1, 2), then spins.
+    const DIGIT_ROM: [u8; 8] = [
+        0x60, 0x01, // v0 := 1
+        0x61, 0x02, // v1 := 2
+        0xD0, 0x15, // sprite v0 v1 5
+        0x12, 0x06, // jump 0x206
+    ];
+
+    fn record(palette: Palette, scale: u32, frames: usize) -> (Cpu, Vec<u8>) {
+        let mut cpu = Cpu::new();
+        cpu.load_rom(&DIGIT_ROM).unwrap();
+        let mut writer = Y4mWriter::new(Vec::new(), palette, scale);
+        for _ in 0..frames {
+            cpu.run_frame().unwrap();
+            writer.record_frame(&cpu).unwrap();
+        }
+        assert_eq!(writer.frames(), frames as u64);
+        (cpu, writer.finish().unwrap())
+    }
+
+    #[test]
+    fn it_converts_colors_to_limited_range() {
+        assert_eq!(ycbcr([0, 0, 0, 0xFF]), [16, 128, 128]);
+        assert_eq!(ycbcr([0xFF, 0xFF, 0xFF, 0xFF]), [235, 128, 128]);
+        assert_eq!(ycbcr([0xFF, 0, 0, 0xFF]), [81, 90, 240]);
+    }
+
+    #[test]
+    fn it_writes_a_header_and_every_frame() {
+        let (_, y4m) = record(Palette::monochrome(), 2, 3);
+        let header = b"YUV4MPEG2 W128 H64 F60:1 Ip A1:1 C444\n";
+        assert_eq!(y4m[..header.len()], header[..]);
+
+        let frame_size = b"FRAME\n".len() + 128 * 64 * 3;
+        let frames = &y4m[header.len()..];
+        assert_eq!(frames.len(), 3 * frame_size);
+        for frame in frames.chunks(frame_size) {
+            assert_eq!(frame[..6], *b"FRAME\n");
+        }
+    }
+
+    #[test]
+    fn it_scales_pixels_in_every_plane() {
+        let palette = Palette::amber();
+        let (cpu, y4m) = record(palette.clone(), 3, 1);
+        let header_len = y4m.iter().position(|&byte| byte == b'\n').unwrap() + 1;
+        let planes = &y4m[header_len + 6..];
+        let (width, height) = (64 * 3, 32 * 3);
+        let (off, on) = (ycbcr(palette.color(0)), ycbcr(palette.color(1)));
+
+        for component in 0..3 {
+            let plane = &planes[component * width * height..][..width * height];
+            for (i, &sample) in plane.iter().enumerate() {
+                let (x, y) = (i % width / 3, i / width / 3);
+                let lit = cpu.display().get_color(y * 64 + x) != 0;
+                let expected = if lit { on } else { off };
+                assert_eq!(sample, expected[component], "at ({}, {})", x, y);
+            }
+        }
+    }
+}
@@ -1,26 +1,132 @@
+use std::env;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::io::Cursor;
 use wasm_chip8::chip8::Cpu;
+use wasm_chip8::display::TextStyle;
+#[cfg(feature = "png")]
+use wasm_chip8::render::Palette;
+use wasm_chip8::wav::WavRecorder;
+
+// Draws the hex digits 0-F across the screen, then spins forever.
+const DIGITS_ROM: [u8; 32] = [
+    0x60, 0x00, // 200: LD V0, 0x00    ; digit
+    0x61, 0x01, // 202: LD V1, 0x01    ; x
+    0x62, 0x01, // 204: LD V2, 0x01    ; y
+    0xF0, 0x29, // 206: LD F, V0
+    0xD1, 0x25, // 208: DRW V1, V2, 5
+    0x71, 0x05, // 20A: ADD V1, 0x05
+    0x70, 0x01, // 20C: ADD V0, 0x01
+    0x40, 0x08, // 20E: SNE V0, 0x08
+    0x12, 0x1A, // 210: JP 0x21A
+    0x30, 0x10, // 212: SE V0, 0x10
+    0x12, 0x06, // 214: JP 0x206
+    0x12, 0x16, // 216: JP 0x216
+    0x00, 0x00, // 218:
+    0x72, 0x07, // 21A: ADD V2, 0x07
+    0x61, 0x01, // 21C: LD V1, 0x01
+    0x12, 0x06, // 21E: JP 0x206
+];
+
+/// The text style named by `CHIP8_TEXT` (`squares`, `braille` or
+/// `half-blocks`), braille by default to keep logs short.
+fn text_style() -> TextStyle {
+    match env::var("CHIP8_TEXT").as_deref() {
+        Ok("squares") => TextStyle::Squares,
+        Ok("half-blocks") => TextStyle::HalfBlocks,
+        _ => TextStyle::Braille,
+    }
+}
+
+/// Loads the ROM named by `CHIP8_ROM`, falling back to the built-in digits demo.
+fn load_rom() -> io::Result<Vec<u8>> {
+    match env::var("CHIP8_ROM") {
+        Ok(path) => {
+            let mut rom = Vec::new();
+            File::open(path)?.read_to_end(&mut rom)?;
+            Ok(rom)
+        }
+        Err(_) => Ok(DIGITS_ROM.to_vec()),
+    }
+}
 
 #[test]
 fn load() -> io::Result<()> {
     let mut cpu = Cpu::new();
-    let mut f = File::open("C:\\Users\\Hemant Hari\\Downloads\\c8games\\HIDDEN")?;
-    //let mut f = File::open("C:\\Users\\Hemant Hari\\Downloads\\BC_test.ch8")?;
-    let mut buffer = [0; 4096];
+    let rom = load_rom()?;
+    let mut buffer = vec![0; 0x200 + rom.len()];
 
-    f.read(&mut buffer[0x200..])?;
+    buffer[0x200..].copy_from_slice(&rom);
 
-    cpu.load_memory(buffer);
+    cpu.load_memory(&buffer)
+        .expect("ROM does not fit in memory");
 
     for _ in 0..3000 {
-        cpu.execute_cycle(10);
+        cpu.step().expect("program crashed");
     }
 
-    cpu.print_display();
+    cpu.print_display(text_style());
 
-    assert!(true);
+    #[cfg(feature = "png")]
+    {
+        let png = cpu.screenshot_png(8, &Palette::default());
+        assert_eq!(&png[1..4], b"PNG");
+        if let Ok(path) = env::var("CHIP8_PNG") {
+            File::create(path)?.write_all(&png)?;
+        }
+    }
+    if let Ok(path) = env::var("CHIP8_PBM") {
+        File::create(path)?.write_all(&cpu.screenshot_pbm())?;
+    }
 
     Ok(())
 }
+
+/// Records three seconds of the ROM's audio, saving it to `CHIP8_WAV` if set.
+#[test]
+fn record_audio() -> io::Result<()> {
+    let mut cpu = Cpu::new();
+    cpu.load_rom(&load_rom()?).expect("ROM too large");
+
+    let mut recorder = WavRecorder::new(Cursor::new(Vec::new()), 44_100)?;
+    for _ in 0..180 {
+        let summary = cpu.run_frame().expect("program crashed");
+        recorder.record_frame(&cpu, &summary)?;
+    }
+    assert_eq!(recorder.samples(), 3 * 44_100);
+    let wav = recorder.finish()?.into_inner();
+
+    if let Ok(path) = env::var("CHIP8_WAV") {
+        File::create(path)?.write_all(&wav)?;
+    }
+
+    Ok(())
+}
+
+/// The digits demo in every text style, each fitting its cell size.
+#[test]
+fn print_text_styles() {
+    let mut cpu = Cpu::new();
+    cpu.load_rom(&DIGITS_ROM).expect("ROM too large");
+    for _ in 0..30 {
+        cpu.run_frame().expect("program crashed");
+    }
+
+    let display = cpu.display();
+    for &(style, columns, lines) in &[
+        (TextStyle::Squares, 64, 32),
+        (TextStyle::Braille, 32, 8),
+        (TextStyle::HalfBlocks, 64, 16),
+    ] {
+        let text = display.render_text(style);
+        assert_eq!(text.lines().count(), lines, "{:?}", style);
+        assert!(text.lines().all(|line| line.chars().count() == columns));
+        cpu.print_display(style);
+    }
+
+    // The left edge of the 0 at (1, 1) fills the right dot column from the
+    // second row down.
+    let braille = display.render_text(TextStyle::Braille);
+    assert_eq!(braille.chars().next(), Some('⢰'));
+}
@@ -1,37 +1,38 @@
-import { Cpu } from 'wasm_chip8';
+import {
+  Cpu, GifRecorder, Palette, Renderer,
+} from 'wasm_chip8';
 import { memory } from 'wasm_chip8/wasm_chip8_bg.wasm';
 import CanvasWrapper from './CanvasWrapper';
 
-// Construct the display, and get its width and height.
 const chip8 = Cpu.new();
-const width = 64;
-const height = 32;
+chip8.seed_random(Math.floor(Math.random() * 0x100000000));
 
-const programMemory = new Uint8Array(
-  memory.buffer,
-  chip8.get_memory(),
-  4096,
-);
-
-const keyboardMemory = new Uint8Array(
-  memory.buffer,
-  chip8.get_keyboard(),
-  16,
-);
-
-// Give the canvas room for all of our cells and a 1px border
-// around each of them.
 const canvas = <HTMLCanvasElement> document.getElementById('game-of-life-canvas');
-const canvasWrapper = new CanvasWrapper(canvas, memory, height, width);
+const canvasWrapper = new CanvasWrapper(canvas, memory);
+const renderer = new Renderer(Palette.classic_green(), 10);
+const recorder = new GifRecorder(Palette.classic_green(), 4);
 
-const renderLoop = () => {
-  for (let i = 0; i < 6; i += 1) {
-    chip8.execute_cycle(Math.floor(Math.random() * (0x100)));
+// Host time already handed to the emulator, in whole microseconds, so the
+// fractions `advance` never sees do not add up to drift.
+let startTime = performance.now();
+let elapsedMicros = 0;
+
+const renderLoop = (now: number) => {
+  const micros = Math.max(0, Math.floor((now - startTime) * 1000) - elapsedMicros);
+  elapsedMicros += micros;
+  try {
+    chip8.advance(micros);
+  } catch (fault) {
+    console.error('CHIP-8 program crashed:', fault);
+    return;
   }
 
-  chip8.decrement_timers();
-  // canvasWrapper.drawGrid();
-  canvasWrapper.drawCells(chip8.get_display());
+  recorder.record_frame(chip8);
+  if (chip8.changed_since_last_frame()) {
+    chip8.clear_dirty();
+    renderer.render(chip8);
+    canvasWrapper.draw(renderer);
+  }
 
   requestAnimationFrame(renderLoop);
 };
@@ -55,12 +56,23 @@ const keyMappings = new Map<string, number>(Object.entries({
   v: 0xF,
 }));
 
+// Queue key changes at the instruction they happened during, counted from
+// the time the next `advance` call starts covering.
+function queueKey(e: KeyboardEvent, pressed: boolean) {
+  if (!keyMappings.has(e.key)) {
+    return;
+  }
+  const sinceAdvance = Math.max(0, e.timeStamp - startTime - elapsedMicros / 1000);
+  const cycle = Math.floor((sinceAdvance * chip8.clock_rate()) / 1000);
+  chip8.queue_key_event(cycle, keyMappings.get(e.key), pressed);
+}
+
 function keyDownHandler(e: KeyboardEvent) {
-  keyboardMemory[keyMappings.get(e.key)] = 1;
+  queueKey(e, true);
 }
 
 function keyUpHandler(e: KeyboardEvent) {
-  keyboardMemory[keyMappings.get(e.key)] = 0;
+  queueKey(e, false);
 }
 
 document.addEventListener('keydown', keyDownHandler, false);
@@ -74,14 +86,36 @@ function readSingleFile(e: InputEvent) {
   const reader = new FileReader();
   reader.onload = function (ev: ProgressEvent<FileReader>) {
     const buffer = ev.target.result as ArrayBuffer;
-    const rom = new DataView(buffer, 0, buffer.byteLength);
-    chip8.reset();
-    for (let i = 0; i < rom.byteLength; i += 1) {
-      programMemory[0x200 + i] = rom.getUint8(i);
+    try {
+      chip8.load_rom(new Uint8Array(buffer));
+    } catch (err) {
+      console.error('Could not load ROM:', err);
+      return;
     }
-    renderLoop();
+    startTime = performance.now();
+    elapsedMicros = 0;
+    requestAnimationFrame(renderLoop);
   };
   reader.readAsArrayBuffer(file);
 }
 
 document.getElementById('file-input').addEventListener('change', readSingleFile, false);
+
+// Starts a clip, or ends it and downloads it as a GIF.
+function toggleRecording(e: MouseEvent) {
+  const button = e.target as HTMLButtonElement;
+  if (!recorder.is_recording()) {
+    recorder.start(chip8);
+    button.textContent = 'Stop recording';
+    return;
+  }
+  const gif = new Blob([recorder.stop()], { type: 'image/gif' });
+  const link = document.createElement('a');
+  link.href = URL.createObjectURL(gif);
+  link.download = 'chip8.gif';
+  link.click();
+  URL.revokeObjectURL(link.href);
+  button.textContent = 'Record GIF';
+}
+
+document.getElementById('record-button').addEventListener('click', toggleRecording, false);